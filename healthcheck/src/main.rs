@@ -1,22 +1,317 @@
-use std::{env, process::ExitCode};
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
-#[inline]
-fn get(url: &str) -> bool {
-    minreq::get(url)
-        .send()
-        .map(|res| {
-            println!("Received status code {}", res.status_code);
-            res
+use clap::{Parser, ValueEnum};
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use serde_json::Value;
+
+/// Checks that a Pixy server is healthy, and exits with a failure code if it is
+/// not. Every option can also be set with an environment variable.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// The scheme of the server, `http` or `https`.
+    #[arg(long, env = "PIXY_HEALTHCHECK_SCHEME", value_enum, default_value_t = Scheme::Http)]
+    scheme: Scheme,
+
+    /// The host of the server.
+    #[arg(long, env = "PIXY_HEALTHCHECK_HOST", default_value = "localhost")]
+    host: String,
+
+    /// The port of the server.
+    #[arg(long, env = "PIXY_PORT", default_value_t = 9147)]
+    port: u16,
+
+    /// The route that is probed.
+    #[arg(long, env = "PIXY_HEALTHCHECK_PATH", default_value = "/healthz")]
+    path: String,
+
+    /// Probes `/readyz` instead of the path.
+    #[arg(long, env = "PIXY_HEALTHCHECK_READY")]
+    ready: bool,
+
+    /// A PEM file of CA certificates that are trusted along with the usual roots,
+    /// for servers with a self-signed or private certificate.
+    #[arg(long, env = "PIXY_HEALTHCHECK_CA_CERT")]
+    ca_cert: Option<PathBuf>,
+
+    /// The name the certificate of the server is checked against, when it is not
+    /// the host.
+    #[arg(long, env = "PIXY_HEALTHCHECK_SERVER_NAME")]
+    server_name: Option<String>,
+
+    /// The Unix socket the server listens on. When set, the request is sent over
+    /// the socket instead of to the host and port.
+    #[arg(long, env = "PIXY_HEALTHCHECK_UNIX_SOCKET")]
+    unix_socket: Option<PathBuf>,
+
+    /// How long to wait for the server, in seconds.
+    #[arg(long, env = "PIXY_HEALTHCHECK_TIMEOUT", default_value_t = 5)]
+    timeout: u64,
+
+    /// A field that the JSON response must have, as `field=value` (i.e.
+    /// `ready=true` or `targets.0.enabled=true`). Can be repeated, or given as a
+    /// comma-separated list in the environment variable.
+    #[arg(long, env = "PIXY_HEALTHCHECK_EXPECT", value_delimiter = ',')]
+    expect: Vec<Expectation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Scheme {
+    Http,
+    Https,
+}
+
+/// A field of the JSON response and the value it must have.
+#[derive(Debug, Clone, PartialEq)]
+struct Expectation {
+    /// The JSON pointer of the field.
+    pointer: String,
+    value: Value,
+}
+
+impl FromStr for Expectation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected field=value, got {}", s))?;
+
+        Ok(Self {
+            pointer: format!("/{}", field.replace('.', "/")),
+            // Values that are not valid JSON are compared as strings
+            value: serde_json::from_str(value).unwrap_or_else(|_| Value::from(value)),
         })
+    }
+}
+
+/// A stream the request can be sent over.
+trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+#[derive(Debug)]
+struct Response {
+    status_code: u16,
+    body: Vec<u8>,
+}
+
+impl Args {
+    fn path(&self) -> &str {
+        if self.ready {
+            "/readyz"
+        } else {
+            &self.path
+        }
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+
+    fn connect(&self) -> Result<Box<dyn Stream>, String> {
+        let stream: Box<dyn Stream> = match &self.unix_socket {
+            Some(path) => {
+                let socket = UnixStream::connect(path)
+                    .map_err(|e| format!("Error connecting to {}: {}", path.display(), e))?;
+
+                socket
+                    .set_read_timeout(Some(self.timeout()))
+                    .and_then(|_| socket.set_write_timeout(Some(self.timeout())))
+                    .map_err(|e| e.to_string())?;
+
+                Box::new(socket)
+            }
+            None => Box::new(self.connect_tcp()?),
+        };
+
+        Ok(stream)
+    }
+
+    fn connect_tcp(&self) -> Result<TcpStream, String> {
+        let addresses = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(|e| format!("Error resolving {}: {}", self.host, e))?;
+
+        let socket = connect_any(addresses, self.timeout())
+            .map_err(|e| e.unwrap_or_else(|| format!("No address found for {}", self.host)))?;
+
+        socket
+            .set_read_timeout(Some(self.timeout()))
+            .and_then(|_| socket.set_write_timeout(Some(self.timeout())))
+            .map_err(|e| e.to_string())?;
+
+        Ok(socket)
+    }
+
+    fn connect_tls(&self, stream: Box<dyn Stream>) -> Result<Box<dyn Stream>, String> {
+        let name = self.server_name.as_deref().unwrap_or(&self.host);
+        let server_name = ServerName::try_from(name.to_string())
+            .map_err(|e| format!("Invalid server name {}: {}", name, e))?;
+
+        let connection =
+            ClientConnection::new(Arc::new(tls_config(self.ca_cert.as_deref())?), server_name)
+                .map_err(|e| e.to_string())?;
+
+        Ok(Box::new(StreamOwned::new(connection, stream)))
+    }
+
+    /// Sends a `GET` request for the path, and reads the whole response.
+    fn request(&self) -> Result<Response, String> {
+        let mut stream = self.connect()?;
+
+        if self.scheme == Scheme::Https {
+            stream = self.connect_tls(stream)?;
+        }
+
+        // HTTP/1.0 responses end when the connection is closed, and are never chunked
+        let request = format!(
+            "GET {} HTTP/1.0\r\nHost: {}:{}\r\nAccept: application/json, */*\r\nUser-Agent: pixy-healthcheck\r\n\r\n",
+            self.path(),
+            self.host,
+            self.port
+        );
+
+        stream
+            .write_all(request.as_bytes())
+            .and_then(|_| stream.flush())
+            .map_err(|e| format!("Error sending the request: {}", e))?;
+
+        let mut bytes = Vec::new();
+
+        match stream.read_to_end(&mut bytes) {
+            Ok(_) => {}
+            // Servers may close the connection without ending the TLS session
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !bytes.is_empty() => {}
+            Err(e) => return Err(format!("Error reading the response: {}", e)),
+        }
+
+        parse_response(&bytes)
+    }
+}
+
+/// Connects to the first of the addresses that accepts the connection, as
+/// `localhost` can resolve to both `::1` and `127.0.0.1` while the server only
+/// listens on one of them. Fails with the error of the last address, if any.
+fn connect_any(
+    addresses: impl IntoIterator<Item = SocketAddr>,
+    timeout: Duration,
+) -> Result<TcpStream, Option<String>> {
+    let mut error = None;
+
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(socket) => return Ok(socket),
+            Err(e) => error = Some(format!("Error connecting to {}: {}", address, e)),
+        }
+    }
+
+    Err(error)
+}
+
+/// Trusts the usual roots, along with the certificates of the CA file.
+fn tls_config(ca_cert: Option<&Path>) -> Result<ClientConfig, String> {
+    let mut roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+
+    if let Some(path) = ca_cert {
+        let file =
+            File::open(path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+
+        for cert in rustls_pemfile::certs(&mut BufReader::new(file)) {
+            let cert =
+                cert.map_err(|e| format!("Invalid certificate {}: {}", path.display(), e))?;
+
+            roots
+                .add(cert)
+                .map_err(|e| format!("Invalid CA certificate {}: {}", path.display(), e))?;
+        }
+    }
+
+    Ok(
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    )
+}
+
+fn parse_response(bytes: &[u8]) -> Result<Response, String> {
+    let end = bytes
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| String::from("Incomplete response"))?;
+
+    let head = String::from_utf8_lossy(&bytes[..end]);
+    let status_code = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| {
+            format!(
+                "Invalid status line {}",
+                head.lines().next().unwrap_or_default()
+            )
+        })?;
+
+    Ok(Response {
+        status_code,
+        body: bytes[end + 4..].to_vec(),
+    })
+}
+
+/// Checks that the response is successful, and has the expected fields.
+fn check_response(response: &Response, expectations: &[Expectation]) -> Result<(), String> {
+    if !(200..=299).contains(&response.status_code) {
+        return Err(format!("Unexpected status code {}", response.status_code));
+    }
+
+    if expectations.is_empty() {
+        return Ok(());
+    }
+
+    let body: Value = serde_json::from_slice(&response.body)
+        .map_err(|e| format!("The response is not JSON: {}", e))?;
+
+    for expectation in expectations {
+        match body.pointer(&expectation.pointer) {
+            Some(value) if value == &expectation.value => {}
+            Some(value) => {
+                return Err(format!(
+                    "Expected {} to be {}, got {}",
+                    expectation.pointer, expectation.value, value
+                ))
+            }
+            None => return Err(format!("Expected {} to be set", expectation.pointer)),
+        }
+    }
+
+    Ok(())
+}
+
+#[inline]
+fn check(args: &Args) -> bool {
+    args.request()
+        .inspect(|res| println!("Received status code {}", res.status_code))
+        .and_then(|res| check_response(&res, &args.expect))
         .map_err(|e| println!("{}", e))
-        .is_ok_and(|res| (200..=299).contains(&res.status_code))
+        .is_ok()
 }
 
 fn main() -> ExitCode {
-    let port = env::var("PIXY_PORT").unwrap_or_else(|_| String::from("8000"));
-    let endpoint = format!("http://localhost:{}/healthz", port);
-
-    if get(&endpoint) {
+    if check(&Args::parse()) {
         ExitCode::SUCCESS
     } else {
         ExitCode::FAILURE
@@ -27,16 +322,28 @@ fn main() -> ExitCode {
 mod tests {
     use super::*;
     use httpmock::MockServer;
+    use std::os::unix::net::UnixListener;
+
+    fn args(server: &MockServer, extra: &[&str]) -> Args {
+        let port = server.port().to_string();
+
+        Args::try_parse_from(
+            ["healthcheck", "--host", "127.0.0.1", "--port", &port]
+                .iter()
+                .chain(extra),
+        )
+        .unwrap()
+    }
 
     fn test_get_failure(status_code: u16, success: bool) {
         let server = MockServer::start();
 
         let mock = server.mock(|when, then| {
-            when.method(httpmock::Method::GET);
+            when.method(httpmock::Method::GET).path("/healthz");
             then.status(status_code);
         });
 
-        let res = get(&server.url("/healthz"));
+        let res = check(&args(&server, &[]));
 
         assert_eq!(res, success);
         mock.assert();
@@ -66,4 +373,147 @@ mod tests {
         failure_when_404: 404, false,
         failure_when_500: 500, false,
     );
+
+    #[test]
+    fn test_connects_to_any_address() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+
+        // A port that nothing listens on anymore
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let timeout = Duration::from_secs(1);
+        let socket = connect_any([closed, open], timeout).unwrap();
+
+        assert_eq!(socket.peer_addr().unwrap(), open);
+        assert!(connect_any([closed], timeout)
+            .unwrap_err()
+            .unwrap()
+            .contains(&closed.to_string()));
+        assert_eq!(connect_any([], timeout).unwrap_err(), None);
+    }
+
+    #[test]
+    fn test_ready_probes_readyz() {
+        let server = MockServer::start();
+
+        let mock = server.mock(|when, then| {
+            when.method(httpmock::Method::GET).path("/readyz");
+            then.status(200);
+        });
+
+        assert!(check(&args(&server, &["--ready"])));
+        mock.assert();
+    }
+
+    #[test]
+    fn test_expected_fields() {
+        let server = MockServer::start();
+
+        server.mock(|when, then| {
+            when.method(httpmock::Method::GET).path("/readyz");
+            then.status(200).json_body(serde_json::json!({
+                "ready": true,
+                "enabledTargets": 2,
+                "targets": [{ "name": "influx" }]
+            }));
+        });
+
+        assert!(check(&args(
+            &server,
+            &[
+                "--path",
+                "/readyz",
+                "--expect",
+                "ready=true",
+                "--expect",
+                "enabledTargets=2,targets.0.name=influx",
+            ]
+        )));
+
+        assert!(!check(&args(
+            &server,
+            &["--path", "/readyz", "--expect", "enabledTargets=3"]
+        )));
+
+        assert!(!check(&args(
+            &server,
+            &["--path", "/readyz", "--expect", "loaded=true"]
+        )));
+    }
+
+    #[test]
+    fn test_expectation_parsing() {
+        let expectation: Expectation = "targets.0.enabled=true".parse().unwrap();
+
+        assert_eq!(expectation.pointer, "/targets/0/enabled");
+        assert_eq!(expectation.value, Value::Bool(true));
+
+        let expectation: Expectation = "name=influx".parse().unwrap();
+
+        assert_eq!(expectation.value, Value::from("influx"));
+        assert!("ready".parse::<Expectation>().is_err());
+    }
+
+    #[test]
+    fn test_unix_socket() {
+        let dir = std::env::temp_dir().join(format!("pixy-healthcheck-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("pixy.sock");
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let read = stream.read(&mut request).unwrap();
+
+            stream
+                .write_all(
+                    b"HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n{\"ready\":true}",
+                )
+                .unwrap();
+
+            String::from_utf8_lossy(&request[..read]).to_string()
+        });
+
+        let args = Args::try_parse_from([
+            "healthcheck",
+            "--unix-socket",
+            path.to_str().unwrap(),
+            "--ready",
+            "--expect",
+            "ready=true",
+        ])
+        .unwrap();
+
+        assert!(check(&args));
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("GET /readyz HTTP/1.0\r\n"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response =
+            parse_response(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 2\r\n\r\n{}")
+                .unwrap();
+
+        assert_eq!(response.status_code, 503);
+        assert_eq!(response.body, b"{}");
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+    }
+
+    #[test]
+    fn test_missing_ca_cert() {
+        assert!(tls_config(Some(Path::new("/nonexistent/ca.pem"))).is_err());
+        assert!(tls_config(None).is_ok());
+    }
 }
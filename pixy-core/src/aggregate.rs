@@ -0,0 +1,352 @@
+//! Aggregation of readings over a time window.
+//!
+//! Targets configured with an `aggregate` window do not receive every reading.
+//! Instead, the statistics of the readings of each device are collected until
+//! the window has elapsed, and a single message summarizing the window is
+//! delivered. Elapsed windows are closed by the next reading of the device, or by
+//! the periodic tick of the gateway if the device goes quiet.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::AggregateConfig;
+use crate::SensorMessage;
+
+/// The statistics of a single field over an aggregation window.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldSummary {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub last: f64,
+}
+
+/// The statistics of every field over an aggregation window. Optional fields are
+/// only present if at least one reading in the window included them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AggregateReadings {
+    pub temperature: FieldSummary,
+    pub pressure: FieldSummary,
+    pub humidity: FieldSummary,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub color_temperature: Option<FieldSummary>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub gas_resistance: Option<FieldSummary>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub aqi: Option<FieldSummary>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub luminance: Option<FieldSummary>,
+}
+
+/// A summary of the readings received from a device during an aggregation window.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AggregateSummary {
+    /// The number of readings in the window.
+    pub count: usize,
+
+    /// The configured length of the window in seconds.
+    pub window: u64,
+
+    /// The timestamp of the first reading in the window.
+    pub first_timestamp: String,
+
+    /// The timestamp of the last reading in the window.
+    pub last_timestamp: String,
+
+    /// The statistics of each field over the window.
+    pub readings: AggregateReadings,
+}
+
+/// The running statistics of a single field.
+#[derive(Debug, Default)]
+struct FieldStats {
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: usize,
+    last: f64,
+}
+
+impl FieldStats {
+    fn add(&mut self, value: Option<f64>) {
+        let Some(value) = value else {
+            return;
+        };
+
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+
+        self.sum += value;
+        self.count += 1;
+        self.last = value;
+    }
+
+    fn summary(&self) -> Option<FieldSummary> {
+        (self.count > 0).then(|| FieldSummary {
+            min: self.min,
+            max: self.max,
+            mean: self.sum / self.count as f64,
+            last: self.last,
+        })
+    }
+}
+
+/// The window of a device, which only keeps the statistics of its readings and
+/// the last reading.
+#[derive(Debug)]
+struct Bucket {
+    started: Instant,
+    count: usize,
+    first_timestamp: String,
+    last: SensorMessage,
+    temperature: FieldStats,
+    pressure: FieldStats,
+    humidity: FieldStats,
+    color_temperature: FieldStats,
+    gas_resistance: FieldStats,
+    aqi: FieldStats,
+    luminance: FieldStats,
+}
+
+impl Bucket {
+    fn new(message: &SensorMessage, started: Instant) -> Self {
+        Self {
+            started,
+            count: 0,
+            first_timestamp: message.timestamp.clone(),
+            last: message.clone(),
+            temperature: FieldStats::default(),
+            pressure: FieldStats::default(),
+            humidity: FieldStats::default(),
+            color_temperature: FieldStats::default(),
+            gas_resistance: FieldStats::default(),
+            aqi: FieldStats::default(),
+            luminance: FieldStats::default(),
+        }
+    }
+
+    fn add(&mut self, message: &SensorMessage) {
+        let readings = &message.readings;
+
+        self.temperature.add(Some(readings.temperature as f64));
+        self.pressure.add(Some(readings.pressure as f64));
+        self.humidity.add(Some(readings.humidity as f64));
+        self.color_temperature
+            .add(readings.color_temperature.map(|v| v as f64));
+        self.gas_resistance
+            .add(readings.gas_resistance.map(|v| v as f64));
+        self.aqi.add(readings.aqi.map(|v| v as f64));
+        self.luminance.add(readings.luminance.map(|v| v as f64));
+
+        self.count += 1;
+        self.last = message.clone();
+    }
+
+    fn summarize(self, window: Duration) -> SensorMessage {
+        let readings = AggregateReadings {
+            temperature: self.temperature.summary().expect("window is never empty"),
+            pressure: self.pressure.summary().expect("window is never empty"),
+            humidity: self.humidity.summary().expect("window is never empty"),
+            color_temperature: self.color_temperature.summary(),
+            gas_resistance: self.gas_resistance.summary(),
+            aqi: self.aqi.summary(),
+            luminance: self.luminance.summary(),
+        };
+
+        let mut last = self.last;
+
+        last.aggregate = Some(AggregateSummary {
+            count: self.count,
+            window: window.as_secs(),
+            first_timestamp: self.first_timestamp,
+            last_timestamp: last.timestamp.clone(),
+            readings,
+        });
+
+        last
+    }
+}
+
+/// Collects readings per device and emits a summary once the window elapses.
+#[derive(Debug)]
+pub(crate) struct Aggregator {
+    window: Duration,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Aggregator {
+    pub(crate) fn new(config: &AggregateConfig) -> Self {
+        Self {
+            window: Duration::from_secs(config.window),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Adds a reading to the window of its device. Returns the aggregated message
+    /// if the window of the device has elapsed.
+    pub(crate) fn push(&self, message: &SensorMessage, now: Instant) -> Option<SensorMessage> {
+        let mut buckets = self.buckets.lock().expect("aggregator lock poisoned");
+
+        let bucket = buckets
+            .entry(message.metadata.uid.clone())
+            .or_insert_with(|| Bucket::new(message, now));
+
+        bucket.add(message);
+
+        if now.duration_since(bucket.started) < self.window {
+            return None;
+        }
+
+        let bucket = buckets.remove(&message.metadata.uid)?;
+
+        Some(bucket.summarize(self.window))
+    }
+
+    /// Closes the windows that have elapsed, returning their aggregated messages.
+    pub(crate) fn expired(&self, now: Instant) -> Vec<SensorMessage> {
+        let mut buckets = self.buckets.lock().expect("aggregator lock poisoned");
+
+        let uids: Vec<String> = buckets
+            .iter()
+            .filter(|(_, bucket)| now.duration_since(bucket.started) >= self.window)
+            .map(|(uid, _)| uid.clone())
+            .collect();
+
+        uids.iter()
+            .filter_map(|uid| buckets.remove(uid))
+            .map(|bucket| bucket.summarize(self.window))
+            .collect()
+    }
+
+    /// Closes every window, whether or not it has elapsed, returning their
+    /// aggregated messages.
+    pub(crate) fn drain(&self) -> Vec<SensorMessage> {
+        let mut buckets = self.buckets.lock().expect("aggregator lock poisoned");
+
+        buckets
+            .drain()
+            .map(|(_, bucket)| bucket.summarize(self.window))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_MESSAGE: &str = include_str!("../../example-configs/test-sensor.json");
+
+    fn message(temperature: f32, timestamp: &str) -> SensorMessage {
+        let mut message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+
+        message.readings.temperature = temperature;
+        message.timestamp = timestamp.to_string();
+
+        message
+    }
+
+    #[test]
+    fn test_aggregator_buffers_until_window_elapses() {
+        let aggregator = Aggregator::new(&AggregateConfig { window: 300 });
+        let start = Instant::now();
+
+        assert!(aggregator.push(&message(20.0, "t0"), start).is_none());
+        assert!(aggregator
+            .push(&message(22.0, "t1"), start + Duration::from_secs(60))
+            .is_none());
+
+        let aggregated = aggregator
+            .push(&message(24.0, "t2"), start + Duration::from_secs(300))
+            .unwrap();
+
+        let summary = aggregated.aggregate.unwrap();
+
+        assert_eq!(summary.count, 3);
+        assert_eq!(summary.window, 300);
+        assert_eq!(summary.first_timestamp, "t0");
+        assert_eq!(summary.last_timestamp, "t2");
+        assert_eq!(
+            summary.readings.temperature,
+            FieldSummary {
+                min: 20.0,
+                max: 24.0,
+                mean: 22.0,
+                last: 24.0,
+            }
+        );
+        assert!(summary.readings.aqi.is_some());
+        assert_eq!(aggregated.readings.temperature, 24.0);
+        assert_eq!(aggregated.timestamp, "t2");
+    }
+
+    #[test]
+    fn test_aggregator_starts_new_window_after_emitting() {
+        let aggregator = Aggregator::new(&AggregateConfig { window: 60 });
+        let start = Instant::now();
+
+        aggregator.push(&message(20.0, "t0"), start);
+        assert!(aggregator
+            .push(&message(20.0, "t1"), start + Duration::from_secs(60))
+            .is_some());
+
+        assert!(aggregator
+            .push(&message(20.0, "t2"), start + Duration::from_secs(61))
+            .is_none());
+    }
+
+    #[test]
+    fn test_aggregator_keeps_devices_separate() {
+        let aggregator = Aggregator::new(&AggregateConfig { window: 60 });
+        let start = Instant::now();
+
+        let mut other = message(30.0, "t0");
+        other.metadata.uid = "other".to_string();
+
+        aggregator.push(&message(20.0, "t0"), start);
+        aggregator.push(&other, start + Duration::from_secs(30));
+
+        let aggregated = aggregator
+            .push(&message(22.0, "t1"), start + Duration::from_secs(60))
+            .unwrap();
+
+        assert_eq!(aggregated.aggregate.unwrap().readings.temperature.max, 22.0);
+    }
+
+    #[test]
+    fn test_aggregator_closes_expired_windows() {
+        let aggregator = Aggregator::new(&AggregateConfig { window: 60 });
+        let start = Instant::now();
+
+        let mut other = message(30.0, "t0");
+        other.metadata.uid = "other".to_string();
+
+        aggregator.push(&message(20.0, "t0"), start);
+        aggregator.push(&message(22.0, "t1"), start + Duration::from_secs(30));
+        aggregator.push(&other, start + Duration::from_secs(30));
+
+        assert!(aggregator
+            .expired(start + Duration::from_secs(59))
+            .is_empty());
+
+        let expired = aggregator.expired(start + Duration::from_secs(60));
+
+        assert_eq!(expired.len(), 1);
+
+        let summary = expired[0].aggregate.as_ref().unwrap();
+
+        assert_eq!(summary.count, 2);
+        assert_eq!(summary.readings.temperature.mean, 21.0);
+
+        // The window of the other device is still open
+        assert_eq!(aggregator.drain().len(), 1);
+        assert!(aggregator.drain().is_empty());
+    }
+}
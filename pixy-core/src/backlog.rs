@@ -0,0 +1,186 @@
+//! Durable storage of the readings that could not be delivered to a target yet.
+//!
+//! Readings are appended to a JSON Lines file, so that they survive restarts, and
+//! are taken out of it all at once when the target can be delivered to again.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::aggregate::AggregateSummary;
+use crate::config::BacklogConfig;
+use crate::derived::DerivedReadings;
+use crate::plausibility::Suspect;
+use crate::SensorMessage;
+
+/// A message along with the fields that the gateway adds to it, which are not
+/// deserialized with the message itself.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct StoredMessage {
+    message: SensorMessage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    received_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    derived: Option<DerivedReadings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aggregate: Option<AggregateSummary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    suspect: Option<Suspect>,
+}
+
+impl From<&SensorMessage> for StoredMessage {
+    fn from(message: &SensorMessage) -> Self {
+        Self {
+            message: message.clone(),
+            received_at: message.received_at,
+            derived: message.derived.clone(),
+            aggregate: message.aggregate.clone(),
+            suspect: message.suspect.clone(),
+        }
+    }
+}
+
+impl From<StoredMessage> for SensorMessage {
+    fn from(stored: StoredMessage) -> Self {
+        SensorMessage {
+            received_at: stored.received_at,
+            derived: stored.derived,
+            aggregate: stored.aggregate,
+            suspect: stored.suspect,
+            ..stored.message
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Backlog {
+    path: PathBuf,
+    max_entries: usize,
+    /// The number of readings in the file.
+    len: Mutex<usize>,
+}
+
+impl Backlog {
+    pub(crate) fn new(config: &BacklogConfig) -> Self {
+        let path = PathBuf::from(&config.path);
+
+        let len = match File::open(&path) {
+            Ok(file) => BufReader::new(file).lines().map_while(Result::ok).count(),
+            Err(_) => {
+                debug!(?path, "No backlog to load");
+                0
+            }
+        };
+
+        Self {
+            path,
+            max_entries: config.max_entries,
+            len: Mutex::new(len),
+        }
+    }
+
+    /// The number of readings waiting in the backlog.
+    pub(crate) fn len(&self) -> usize {
+        *self.len.lock().expect("backlog lock poisoned")
+    }
+
+    /// Stores a message, returning `false` if it could not be stored.
+    pub(crate) fn push(&self, message: &SensorMessage) -> bool {
+        let mut len = self.len.lock().expect("backlog lock poisoned");
+
+        if *len >= self.max_entries {
+            warn!(path = ?self.path, "Backlog is full");
+            return false;
+        }
+
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| {
+                let line = serde_json::to_string(&StoredMessage::from(message))
+                    .map_err(std::io::Error::other)?;
+                writeln!(file, "{}", line)
+            });
+
+        match result {
+            Ok(()) => {
+                *len += 1;
+                true
+            }
+            Err(e) => {
+                warn!(path = ?self.path, error = %e, "Failed to store reading in backlog");
+                false
+            }
+        }
+    }
+
+    /// Takes all the messages out of the backlog, oldest first.
+    pub(crate) fn drain(&self) -> Vec<SensorMessage> {
+        let mut len = self.len.lock().expect("backlog lock poisoned");
+
+        let Ok(file) = File::open(&self.path) else {
+            *len = 0;
+            return Vec::new();
+        };
+
+        let messages = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str::<StoredMessage>(&line).ok())
+            .map(SensorMessage::from)
+            .collect();
+
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!(path = ?self.path, error = %e, "Failed to clear backlog");
+        }
+
+        *len = 0;
+
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_MESSAGE: &str = include_str!("../../example-configs/test-sensor.json");
+
+    #[test]
+    fn test_backlog_round_trip() {
+        let path = std::env::temp_dir().join(format!("pixy-backlog-{}.jsonl", std::process::id()));
+        let config = BacklogConfig {
+            path: path.to_string_lossy().to_string(),
+            max_entries: 2,
+        };
+
+        let _ = std::fs::remove_file(&path);
+
+        let mut message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+        message.derived = Some(DerivedReadings::compute(&message.readings, None));
+
+        let backlog = Backlog::new(&config);
+
+        assert!(backlog.push(&message));
+        assert!(backlog.push(&message));
+        assert!(!backlog.push(&message));
+
+        // The stored readings survive restarts
+        let backlog = Backlog::new(&config);
+
+        assert_eq!(backlog.len(), 2);
+
+        let messages = backlog.drain();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].derived, message.derived);
+        assert_eq!(backlog.len(), 0);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,201 @@
+//! Circuit breakers that stop deliveries to targets that keep failing.
+//!
+//! The circuit of a target opens after a number of consecutive failed deliveries,
+//! and readings are not delivered to it while it is open. Once the cooldown has
+//! passed, a single delivery is let through to probe the target: the circuit
+//! closes again if it succeeds, and stays open for another cooldown if it fails.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::backlog::Backlog;
+use crate::config::CircuitBreakerConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CircuitState {
+    /// Readings are delivered to the target.
+    Closed,
+    /// Readings are not delivered to the target.
+    Open,
+    /// A delivery is probing whether the target has recovered.
+    HalfOpen,
+}
+
+impl CircuitState {
+    /// The value of the state in the metrics.
+    pub(crate) fn gauge(&self) -> u64 {
+        match self {
+            CircuitState::Closed => 0,
+            CircuitState::Open => 1,
+            CircuitState::HalfOpen => 2,
+        }
+    }
+}
+
+impl std::fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CircuitState::Closed => write!(f, "closed"),
+            CircuitState::Open => write!(f, "open"),
+            CircuitState::HalfOpen => write!(f, "half-open"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    circuit: CircuitState,
+    /// The number of consecutive failed deliveries.
+    failures: u32,
+    /// When the circuit last opened.
+    opened_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+    pub(crate) backlog: Option<Backlog>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            failure_threshold: config.failure_threshold.max(1),
+            cooldown: Duration::from_secs(config.cooldown),
+            state: Mutex::new(State {
+                circuit: CircuitState::Closed,
+                failures: 0,
+                opened_at: None,
+            }),
+            backlog: config.backlog.as_ref().map(Backlog::new),
+        }
+    }
+
+    /// The current state of the circuit.
+    pub(crate) fn state(&self) -> CircuitState {
+        self.state
+            .lock()
+            .expect("circuit breaker lock poisoned")
+            .circuit
+    }
+
+    /// Whether a delivery can be made. When the cooldown of an open circuit has
+    /// passed, the delivery is allowed as the probe, and the circuit is half-open
+    /// until its result is recorded.
+    pub(crate) fn allow(&self, now: Instant) -> bool {
+        let mut state = self.state.lock().expect("circuit breaker lock poisoned");
+
+        match state.circuit {
+            CircuitState::Closed => true,
+            CircuitState::HalfOpen => false,
+            CircuitState::Open => {
+                let cooled_down = state
+                    .opened_at
+                    .is_none_or(|opened_at| now >= opened_at + self.cooldown);
+
+                if cooled_down {
+                    state.circuit = CircuitState::HalfOpen;
+                }
+
+                cooled_down
+            }
+        }
+    }
+
+    /// Records a successful delivery, closing the circuit.
+    pub(crate) fn record_success(&self) {
+        let mut state = self.state.lock().expect("circuit breaker lock poisoned");
+
+        if state.circuit != CircuitState::Closed {
+            info!("Target recovered, closing circuit");
+        }
+
+        state.circuit = CircuitState::Closed;
+        state.failures = 0;
+        state.opened_at = None;
+    }
+
+    /// Records a failed delivery, opening the circuit if there were too many of
+    /// them in a row, or if the delivery was the probe.
+    pub(crate) fn record_failure(&self, now: Instant) {
+        let mut state = self.state.lock().expect("circuit breaker lock poisoned");
+
+        state.failures = state.failures.saturating_add(1);
+
+        let open = match state.circuit {
+            CircuitState::Closed => state.failures >= self.failure_threshold,
+            CircuitState::HalfOpen => true,
+            // Deliveries that started before the circuit opened can still fail
+            CircuitState::Open => false,
+        };
+
+        if open {
+            warn!(
+                failures = state.failures,
+                cooldown = ?self.cooldown,
+                "Target keeps failing, opening circuit"
+            );
+
+            state.circuit = CircuitState::Open;
+            state.opened_at = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(config: &str) -> CircuitBreaker {
+        CircuitBreaker::new(&serde_yaml::from_str(config).unwrap())
+    }
+
+    #[test]
+    fn test_circuit_opens_after_consecutive_failures() {
+        let breaker = breaker("{ failureThreshold: 3, cooldown: 60 }");
+        let now = Instant::now();
+
+        breaker.record_failure(now);
+        breaker.record_failure(now);
+        breaker.record_success();
+        breaker.record_failure(now);
+        breaker.record_failure(now);
+
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allow(now));
+
+        breaker.record_failure(now);
+
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow(now + Duration::from_secs(59)));
+    }
+
+    #[test]
+    fn test_single_probe_after_cooldown() {
+        let breaker = breaker("{ failureThreshold: 1, cooldown: 60 }");
+        let now = Instant::now();
+
+        breaker.record_failure(now);
+
+        let later = now + Duration::from_secs(60);
+
+        assert!(breaker.allow(later));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(!breaker.allow(later));
+
+        // A failed probe opens the circuit for another cooldown
+        breaker.record_failure(later);
+
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow(later + Duration::from_secs(59)));
+        assert!(breaker.allow(later + Duration::from_secs(60)));
+
+        breaker.record_success();
+
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
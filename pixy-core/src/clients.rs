@@ -1,9 +1,418 @@
-use reqwest::{Client, ClientBuilder};
+use minijinja::{value::Value, Environment};
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+use reqwest::{Certificate, Identity};
+use reqwest::{Client, ClientBuilder, NoProxy, Proxy};
 use std::time::Duration;
 
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+use crate::config::TlsVersion;
+use crate::config::{ProxyConfig, TlsConfig};
+
 pub(crate) fn get_default_webhook_client() -> Client {
     ClientBuilder::new()
         .timeout(Duration::from_secs(60))
         .build()
         .expect("Failed to build reqwest client")
 }
+
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))
+}
+
+/// The PEM contents of a client certificate and its private key.
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+struct ClientFiles {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+}
+
+/// Reads the client certificate and its key, if both are configured.
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+fn client_files(tls: &TlsConfig) -> Result<Option<ClientFiles>, String> {
+    match (&tls.client_cert, &tls.client_key) {
+        (Some(cert), Some(key)) => Ok(Some(ClientFiles {
+            cert: read_file(cert)?,
+            key: read_file(key)?,
+        })),
+        (None, None) => Ok(None),
+        _ => Err(String::from(
+            "clientCert and clientKey must be set together",
+        )),
+    }
+}
+
+/// Parses a SHA-256 fingerprint written in hex, optionally separated by colons as
+/// printed by `openssl x509 -fingerprint -sha256`.
+#[cfg_attr(not(feature = "rustls-tls"), allow(dead_code))]
+fn parse_pin(pin: &str) -> Result<Vec<u8>, String> {
+    let fingerprint = hex::decode(pin.replace(':', ""))
+        .map_err(|_| format!("Invalid certificate pin: {}", pin))?;
+
+    if fingerprint.len() != 32 {
+        return Err(format!(
+            "Certificate pin is not a SHA-256 fingerprint: {}",
+            pin
+        ));
+    }
+
+    Ok(fingerprint)
+}
+
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+impl From<TlsVersion> for reqwest::tls::Version {
+    fn from(version: TlsVersion) -> Self {
+        match version {
+            TlsVersion::Tls12 => reqwest::tls::Version::TLS_1_2,
+            TlsVersion::Tls13 => reqwest::tls::Version::TLS_1_3,
+        }
+    }
+}
+
+#[cfg(feature = "rustls-tls")]
+fn identity(cert: &[u8], key: &[u8]) -> Result<Identity, String> {
+    Identity::from_pem(&[cert, b"\n", key].concat()).map_err(|e| e.to_string())
+}
+
+#[cfg(all(not(feature = "rustls-tls"), feature = "native-tls"))]
+fn identity(cert: &[u8], key: &[u8]) -> Result<Identity, String> {
+    Identity::from_pkcs8_pem(cert, key).map_err(|e| e.to_string())
+}
+
+/// Builds a client for targets with their own TLS or proxy settings.
+///
+/// ## Arguments
+///
+/// * `tls` - The TLS settings of the target, if any.
+/// * `proxy` - The proxy to use, if any.
+/// * `context` - The context that the proxy settings are rendered with.
+pub(crate) fn get_webhook_client(
+    tls: Option<&TlsConfig>,
+    proxy: Option<&ProxyConfig>,
+    context: &Value,
+) -> Result<Client, String> {
+    let mut builder = ClientBuilder::new().timeout(Duration::from_secs(60));
+
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(build_proxy(proxy, context)?);
+    }
+
+    if let Some(tls) = tls {
+        builder = if tls.pins.is_empty() {
+            configure_tls(builder, tls)?
+        } else {
+            pinning::configure_tls(builder, tls)?
+        };
+    }
+
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Builds a proxy from its settings. Setting a proxy explicitly disables the
+/// proxies read from the environment.
+fn build_proxy(config: &ProxyConfig, context: &Value) -> Result<Proxy, String> {
+    let env = Environment::new();
+    let render = |template: &str| {
+        env.render_str(template, context)
+            .map_err(|e| format!("Error rendering proxy settings: {}", e))
+    };
+
+    let mut proxy = Proxy::all(render(&config.url)?)
+        .map_err(|e| format!("Invalid proxy URL {}: {}", config.url, e))?;
+
+    if let Some(username) = &config.username {
+        let password = match &config.password {
+            Some(password) => render(password)?,
+            None => String::new(),
+        };
+
+        proxy = proxy.basic_auth(&render(username)?, &password);
+    }
+
+    Ok(proxy.no_proxy(NoProxy::from_string(&config.no_proxy.join(","))))
+}
+
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+fn configure_tls(builder: ClientBuilder, tls: &TlsConfig) -> Result<ClientBuilder, String> {
+    // The identity of the client is specific to the TLS backend, so make sure the
+    // backend it was read for is the one in use
+    #[cfg(feature = "rustls-tls")]
+    let mut builder = builder.use_rustls_tls();
+    #[cfg(not(feature = "rustls-tls"))]
+    let mut builder = builder;
+
+    for path in &tls.ca_certs {
+        let certs = Certificate::from_pem_bundle(&read_file(path)?)
+            .map_err(|e| format!("Invalid CA certificate {}: {}", path, e))?;
+
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    if let Some(files) = client_files(tls)? {
+        builder = builder.identity(identity(&files.cert, &files.key)?);
+    }
+
+    if let Some(version) = tls.min_version {
+        builder = builder.min_tls_version(version.into());
+    }
+
+    Ok(builder.danger_accept_invalid_certs(tls.danger_accept_invalid_certs))
+}
+
+#[cfg(not(any(feature = "rustls-tls", feature = "native-tls")))]
+fn configure_tls(_builder: ClientBuilder, _tls: &TlsConfig) -> Result<ClientBuilder, String> {
+    Err(String::from(
+        "TLS settings require the rustls-tls or native-tls feature",
+    ))
+}
+
+/// Certificate pinning is not supported by reqwest, so pinned targets use a rustls
+/// configuration built from scratch with a verifier that checks the pins.
+#[cfg(feature = "rustls-tls")]
+mod pinning {
+    use std::io::BufReader;
+    use std::sync::Arc;
+
+    use reqwest::ClientBuilder;
+    use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+    use rustls::client::WebPkiServerVerifier;
+    use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+    use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+    use sha2::{Digest, Sha256};
+
+    use super::{client_files, parse_pin, read_file};
+    use crate::config::{TlsConfig, TlsVersion};
+
+    #[derive(Debug)]
+    struct PinnedVerifier {
+        inner: Arc<WebPkiServerVerifier>,
+        pins: Vec<Vec<u8>>,
+        verify_chain: bool,
+    }
+
+    impl ServerCertVerifier for PinnedVerifier {
+        fn verify_server_cert(
+            &self,
+            end_entity: &CertificateDer<'_>,
+            intermediates: &[CertificateDer<'_>],
+            server_name: &ServerName<'_>,
+            ocsp_response: &[u8],
+            now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            let fingerprint = Sha256::digest(end_entity.as_ref());
+
+            if !self.pins.iter().any(|pin| pin[..] == fingerprint[..]) {
+                return Err(rustls::Error::General(String::from(
+                    "Server certificate does not match any pin",
+                )));
+            }
+
+            if !self.verify_chain {
+                return Ok(ServerCertVerified::assertion());
+            }
+
+            self.inner.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            )
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            self.inner.verify_tls12_signature(message, cert, dss)
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            self.inner.verify_tls13_signature(message, cert, dss)
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.inner.supported_verify_schemes()
+        }
+    }
+
+    fn read_certs(contents: &[u8], path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+        rustls_pemfile::certs(&mut BufReader::new(contents))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid certificate {}: {}", path, e))
+    }
+
+    pub(super) fn configure_tls(
+        builder: ClientBuilder,
+        tls: &TlsConfig,
+    ) -> Result<ClientBuilder, String> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+        let mut roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+
+        for path in &tls.ca_certs {
+            for cert in read_certs(&read_file(path)?, path)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("Invalid CA certificate {}: {}", path, e))?;
+            }
+        }
+
+        let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let verifier = PinnedVerifier {
+            inner,
+            pins: tls
+                .pins
+                .iter()
+                .map(|pin| parse_pin(pin))
+                .collect::<Result<_, _>>()?,
+            verify_chain: !tls.danger_accept_invalid_certs,
+        };
+
+        let versions: &[&rustls::SupportedProtocolVersion] = match tls.min_version {
+            Some(TlsVersion::Tls13) => &[&rustls::version::TLS13],
+            _ => rustls::DEFAULT_VERSIONS,
+        };
+
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(versions)
+            .map_err(|e| e.to_string())?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier));
+
+        let config = match client_files(tls)? {
+            Some(files) => {
+                let key_path = tls.client_key.as_deref().unwrap_or_default();
+                let key = rustls_pemfile::private_key(&mut BufReader::new(&files.key[..]))
+                    .ok()
+                    .flatten()
+                    .ok_or_else(|| format!("Invalid private key {}", key_path))?;
+
+                config
+                    .with_client_auth_cert(
+                        read_certs(&files.cert, tls.client_cert.as_deref().unwrap_or_default())?,
+                        key,
+                    )
+                    .map_err(|e| e.to_string())?
+            }
+            None => config.with_no_client_auth(),
+        };
+
+        Ok(builder.use_preconfigured_tls(config))
+    }
+}
+
+#[cfg(not(feature = "rustls-tls"))]
+mod pinning {
+    use reqwest::ClientBuilder;
+
+    use crate::config::TlsConfig;
+
+    pub(super) fn configure_tls(
+        _builder: ClientBuilder,
+        _tls: &TlsConfig,
+    ) -> Result<ClientBuilder, String> {
+        Err(String::from(
+            "Certificate pinning requires the rustls-tls feature",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIN: &str = "5E:88:48:98:DA:28:04:71:51:D0:E5:6F:8D:C6:29:27:73:60:3D:0D:6A:AB:BD:D6:2A:11:EF:72:1D:15:42:D8";
+
+    fn tls(config: &str) -> TlsConfig {
+        serde_yaml::from_str(config).unwrap()
+    }
+
+    #[test]
+    fn test_parse_pin() {
+        assert_eq!(parse_pin(PIN).unwrap().len(), 32);
+        assert_eq!(
+            parse_pin(&PIN.replace(':', "").to_lowercase()),
+            parse_pin(PIN)
+        );
+        assert!(parse_pin("5E:88:48").is_err());
+        assert!(parse_pin("not a pin").is_err());
+    }
+
+    fn proxy(config: &str) -> ProxyConfig {
+        serde_yaml::from_str(config).unwrap()
+    }
+
+    #[test]
+    fn test_client_with_proxy() {
+        let ctx = minijinja::context!(env => minijinja::context!(PROXY_HOST => "proxy.local"));
+
+        for url in [
+            "http://{{ env.PROXY_HOST }}:3128",
+            "https://{{ env.PROXY_HOST }}:3128",
+            "socks5://localhost:1080",
+            "socks5h://127.0.0.1:1080",
+        ] {
+            let config = proxy(&format!(
+                "{{ url: '{}', username: pixy, password: secret, noProxy: [localhost, 10.0.0.0/8] }}",
+                url
+            ));
+
+            assert!(get_webhook_client(None, Some(&config), &ctx).is_ok());
+        }
+
+        let result = get_webhook_client(None, Some(&proxy("{ url: 'not a url' }")), &ctx);
+
+        assert!(result.unwrap_err().starts_with("Invalid proxy URL"));
+    }
+
+    fn tls_client(config: &str) -> Result<Client, String> {
+        get_webhook_client(Some(&tls(config)), None, &Value::UNDEFINED)
+    }
+
+    #[test]
+    #[cfg(feature = "rustls-tls")]
+    fn test_client_with_tls_settings() {
+        assert!(tls_client("{ minVersion: '1.3', dangerAcceptInvalidCerts: true }").is_ok());
+        assert!(tls_client(&format!("{{ pins: ['{}'] }}", PIN)).is_ok());
+    }
+
+    #[test]
+    #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+    fn test_client_with_missing_files() {
+        let result = tls_client("{ caCerts: [/does/not/exist.pem] }");
+
+        assert!(result
+            .unwrap_err()
+            .starts_with("Failed to read /does/not/exist.pem"));
+
+        let result = tls_client("{ clientCert: /does/not/exist.pem }");
+
+        assert_eq!(
+            result.unwrap_err(),
+            "clientCert and clientKey must be set together"
+        );
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "rustls-tls", feature = "native-tls")))]
+    fn test_client_with_tls_settings_without_backend() {
+        assert!(tls_client("{ minVersion: '1.3' }")
+            .unwrap_err()
+            .contains("feature"));
+    }
+}
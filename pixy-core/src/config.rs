@@ -1,9 +1,305 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ConfigFile {
     pub targets: Vec<Target>,
+
+    /// Per-device settings, keyed by the `uid` of the board.
+    #[serde(default)]
+    pub devices: HashMap<String, DeviceConfig>,
+
+    /// Suppresses readings that were already received.
+    #[serde(default)]
+    pub deduplication: Option<DeduplicationConfig>,
+
+    /// Checks that the values of the readings are plausible.
+    #[serde(default)]
+    pub plausibility: Option<PlausibilityConfig>,
+
+    /// How to handle the timestamps sent by the sensors. Timestamps are passed
+    /// through as they were sent when unset.
+    #[serde(default)]
+    pub timestamps: Option<TimestampConfig>,
+
+    /// Verifies the signatures of the payloads sent to the gateway.
+    #[serde(default)]
+    pub verification: Option<VerificationConfig>,
+
+    /// The proxy used to reach the targets, unless they set their own.
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+
+    /// Where the readings that could not be delivered to a target are sent.
+    #[serde(default)]
+    pub dead_letter: Option<DeadLetterConfig>,
+
+    /// The settings of the server, which the command line and the environment
+    /// take precedence over.
+    #[serde(default)]
+    pub server: Option<ServerConfig>,
+}
+
+/// The settings of the server. Every setting is optional, so that it can be
+/// left to the environment or the defaults of the server.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerConfig {
+    #[serde(default)]
+    pub port: Option<u16>,
+
+    /// The addresses to listen on, `host:port` or `unix:/path/to/socket`.
+    #[serde(default)]
+    pub listen: Option<Vec<String>>,
+
+    /// The permissions of the Unix sockets, in octal.
+    #[serde(default)]
+    pub socket_mode: Option<String>,
+
+    /// The PEM file of the certificate chain served over TLS on TCP addresses.
+    #[serde(default)]
+    pub tls_cert: Option<String>,
+
+    /// The PEM file of the private key of the TLS certificate.
+    #[serde(default)]
+    pub tls_key: Option<String>,
+
+    #[serde(default)]
+    pub enable_echo: Option<bool>,
+
+    #[serde(default)]
+    pub enable_dashboard: Option<bool>,
+
+    /// Whether to stream the readings on `/stream` and `/ws`.
+    #[serde(default)]
+    pub enable_stream: Option<bool>,
+
+    /// The most clients that can stream the readings at once.
+    #[serde(default)]
+    pub stream_max_clients: Option<u64>,
+
+    /// The largest reading accepted on `/data`, in kilobytes.
+    #[serde(default)]
+    pub max_payload_size: Option<u64>,
+
+    /// The bearer token of the admin API.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+
+    #[serde(default)]
+    pub log_level: Option<String>,
+
+    #[serde(default)]
+    pub log_format: Option<String>,
+
+    #[serde(default)]
+    pub log_file: Option<String>,
+
+    /// The size in megabytes after which the log file is rotated.
+    #[serde(default)]
+    pub log_max_size: Option<u64>,
+
+    #[serde(default)]
+    pub log_max_files: Option<u64>,
+
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+
+    #[serde(default)]
+    pub otlp_protocol: Option<String>,
+
+    /// The fewest enabled targets for the gateway to be ready.
+    #[serde(default)]
+    pub ready_min_targets: Option<u64>,
+
+    /// The most failing targets for the gateway to be ready.
+    #[serde(default)]
+    pub ready_max_failing: Option<u64>,
+
+    /// The most readings waiting in the backlog of a target for the gateway to be
+    /// ready.
+    #[serde(default)]
+    pub ready_max_backlog: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterConfig {
+    /// The JSON Lines file that dead letters are appended to.
+    #[serde(default)]
+    pub path: Option<String>,
+
+    /// The name of the target that dead letters are sent to. The target only
+    /// receives dead letters.
+    #[serde(default)]
+    pub target: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProxyConfig {
+    /// The URL of the proxy, using the `http`, `https` or `socks5` scheme. Supports
+    /// context objects.
+    pub url: String,
+
+    /// The username to authenticate with the proxy. Supports context objects.
+    #[serde(default)]
+    pub username: Option<String>,
+
+    /// The password to authenticate with the proxy. Supports context objects.
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// The hosts, domains and IP ranges that are reached without the proxy.
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TimestampConfig {
+    /// The largest difference in seconds between the timestamp of a reading and the
+    /// time it was received before its timestamp is considered skewed.
+    #[serde(default)]
+    pub max_skew: Option<u64>,
+
+    /// What to do with readings whose timestamp is skewed or cannot be parsed.
+    #[serde(default)]
+    pub on_skew: OnSkew,
+
+    /// The oldest a reading can be, in seconds, before it is rejected.
+    #[serde(default)]
+    pub max_age: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum OnSkew {
+    /// Keeps the timestamp sent by the sensor.
+    #[default]
+    Keep,
+    /// Replaces the timestamp with the time the reading was received.
+    Replace,
+    /// Rejects the reading with a `422 Unprocessable Entity` response.
+    Reject,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PlausibilityConfig {
+    /// The limits of each field of the readings, keyed by the name of the field.
+    #[serde(default)]
+    pub fields: HashMap<String, FieldLimits>,
+
+    /// What to do with readings that are not plausible.
+    #[serde(default)]
+    pub on_violation: OnViolation,
+
+    /// The targets that implausible readings are sent to when routing them.
+    #[serde(default)]
+    pub route_to: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldLimits {
+    /// The smallest valid value.
+    #[serde(default)]
+    pub min: Option<f64>,
+
+    /// The largest valid value.
+    #[serde(default)]
+    pub max: Option<f64>,
+
+    /// The largest valid change per minute, compared to the last plausible reading
+    /// of the same device.
+    #[serde(default)]
+    pub max_rate: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum OnViolation {
+    /// Rejects the reading with a `422 Unprocessable Entity` response.
+    #[default]
+    Reject,
+    /// Accepts the reading, but does not send it to any target.
+    Drop,
+    /// Sends the reading to every target, marked as suspect.
+    Tag,
+    /// Sends the reading, marked as suspect, only to the targets in `route_to`.
+    Route,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeduplicationConfig {
+    /// How long a reading is remembered for, in seconds.
+    #[serde(default = "_default_dedup_ttl")]
+    pub ttl: u64,
+
+    /// The file to persist the remembered readings to, if any.
+    #[serde(default)]
+    pub persist: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceConfig {
+    /// The altitude of the device in meters, used to compute the sea-level pressure.
+    #[serde(default)]
+    pub altitude: Option<f32>,
+
+    /// The number of seconds to add to the timestamps sent by the device, to
+    /// correct a real-time clock that is known to be wrong.
+    #[serde(default)]
+    pub clock_offset: i64,
+
+    /// The shared secret used to verify the payloads signed by the device.
+    /// Supports context objects.
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationConfig {
+    /// Whether payloads without a signature are rejected.
+    #[serde(default = "_default_true")]
+    pub required: bool,
+
+    /// The hash algorithm of the HMAC.
+    #[serde(default)]
+    pub algorithm: SigningAlgorithm,
+
+    /// The header the signature is sent in.
+    #[serde(default = "_default_signature_header")]
+    pub header: String,
+
+    /// The header the Unix timestamp of the request is sent in.
+    #[serde(default = "_default_timestamp_header")]
+    pub timestamp_header: String,
+
+    /// The header identifying which of the `keys` the payload was signed with.
+    #[serde(default = "_default_key_id_header")]
+    pub key_id_header: String,
+
+    /// The content that is signed.
+    #[serde(default)]
+    pub canonical: CanonicalFormat,
+
+    /// The maximum difference between the signed timestamp and the time the
+    /// payload was received, in seconds. Cannot be set when only the body is
+    /// signed, since the timestamp is not part of the signature then.
+    #[serde(default)]
+    pub tolerance: Option<u64>,
+
+    /// Shared secrets that are not tied to a device, keyed by their id. These
+    /// support context objects.
+    #[serde(default)]
+    pub keys: HashMap<String, String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -12,23 +308,109 @@ pub struct Target {
     pub name: String,
     #[serde(default = "_default_true")]
     pub enabled: bool,
+    /// Whether the derived values should be merged into the forwarded payload.
+    #[serde(default)]
+    pub include_derived: bool,
+    /// Aggregates readings over a window instead of forwarding each of them.
+    #[serde(default)]
+    pub aggregate: Option<AggregateConfig>,
+    /// Limits how often deliveries can be made to the target.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Stops delivering to the target while it keeps failing.
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
     #[serde(flatten)]
     pub properties: TargetProperties,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AggregateConfig {
+    /// The length of the aggregation window, in seconds.
+    pub window: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitConfig {
+    /// The maximum number of deliveries that can be made in a period.
+    #[serde(default)]
+    pub max_requests: Option<u32>,
+
+    /// The length of the period, in seconds.
+    #[serde(default = "_default_period")]
+    pub period: u64,
+
+    /// The number of deliveries that can be made at once. Defaults to `max_requests`.
+    #[serde(default)]
+    pub burst: Option<u32>,
+
+    /// The minimum number of seconds between two deliveries for the same device.
+    #[serde(default)]
+    pub min_interval: Option<u64>,
+
+    /// What to do with readings that are over the limit.
+    #[serde(default)]
+    pub on_limit: OnLimit,
+
+    /// The maximum number of deliveries waiting for a slot when queueing.
+    #[serde(default = "_default_max_queue")]
+    pub max_queue: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitBreakerConfig {
+    /// The number of consecutive failed deliveries after which the circuit opens.
+    #[serde(default = "_default_failure_threshold")]
+    pub failure_threshold: u32,
+
+    /// How long the circuit stays open before a delivery is tried again, in seconds.
+    #[serde(default = "_default_cooldown")]
+    pub cooldown: u64,
+
+    /// Stores the readings received while the circuit is open, to deliver them once
+    /// it closes. Readings are discarded when this is not set.
+    #[serde(default)]
+    pub backlog: Option<BacklogConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BacklogConfig {
+    /// The file that the readings are stored in.
+    pub path: String,
+
+    /// The maximum number of readings stored. Newer readings are discarded once
+    /// the backlog is full.
+    #[serde(default = "_default_max_backlog")]
+    pub max_entries: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum OnLimit {
+    /// Discards the reading.
+    #[default]
+    Drop,
+    /// Delivers only the latest reading of each device once the limit allows it.
+    Coalesce,
+    /// Delivers every reading once the limit allows it.
+    Queue,
+}
+
+// Targets are only built once from the configuration, so the size of the variants
+// does not matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub enum TargetProperties {
     Webhook(WebhookTargetProperties),
+    #[default]
     Unknown,
 }
 
-impl Default for TargetProperties {
-    fn default() -> Self {
-        Self::Unknown
-    }
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WebhookTargetProperties {
     pub url: String,
@@ -38,15 +420,283 @@ pub struct WebhookTargetProperties {
     #[serde(default = "_default_timeout")]
     pub timeout: u8,
 
+    /// How failed requests are retried.
+    #[serde(default)]
+    pub retry: Option<RetryConfig>,
+
     #[serde(default)]
     pub auth: Option<WebhookAuth>,
+
+    /// Signs the payload so that the receiver can verify where it came from.
+    #[serde(default)]
+    pub signing: Option<WebhookSigning>,
+
+    /// The TLS settings used to connect to the webhook.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+
+    /// The proxy used to reach the webhook, instead of the global one.
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryConfig {
+    /// The delay before the first retry, in seconds.
+    #[serde(default = "_default_min_backoff")]
+    pub min_backoff: f64,
+
+    /// The longest delay between two retries, in seconds.
+    #[serde(default = "_default_max_backoff")]
+    pub max_backoff: f64,
+
+    /// How the delays between retries are randomised.
+    #[serde(default)]
+    pub jitter: RetryJitter,
+
+    /// The longest time that a reading is retried for, in seconds.
+    #[serde(default)]
+    pub deadline: Option<u64>,
+
+    /// The response status codes that are retried. When unset, server errors,
+    /// `408 Request Timeout` and `429 Too Many Requests` are retried.
+    #[serde(default)]
+    pub status_codes: Option<Vec<u16>>,
+
+    /// Whether requests that time out are retried.
+    #[serde(default = "_default_true")]
+    pub retry_timeouts: bool,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            min_backoff: _default_min_backoff(),
+            max_backoff: _default_max_backoff(),
+            jitter: RetryJitter::default(),
+            deadline: None,
+            status_codes: None,
+            retry_timeouts: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum RetryJitter {
+    /// Waits exactly the backoff.
+    None,
+    /// Waits between zero and the backoff.
+    #[default]
+    Full,
+    /// Waits between `minBackoff` and the backoff.
+    Bounded,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsConfig {
+    /// The PEM file holding the client certificate, for mutual TLS.
+    #[serde(default)]
+    pub client_cert: Option<String>,
+
+    /// The PEM file holding the private key of the client certificate.
+    #[serde(default)]
+    pub client_key: Option<String>,
+
+    /// PEM files holding certificate authorities to trust, in addition to the
+    /// default ones.
+    #[serde(default)]
+    pub ca_certs: Vec<String>,
+
+    /// The SHA-256 fingerprints of the server certificates to accept, in hex.
+    #[serde(default)]
+    pub pins: Vec<String>,
+
+    /// The lowest version of TLS to accept.
+    #[serde(default)]
+    pub min_version: Option<TlsVersion>,
+
+    /// Accepts any server certificate, even if it is expired, self-signed, or for
+    /// another host. Only meant for lab servers.
+    #[serde(default)]
+    pub danger_accept_invalid_certs: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookSigning {
+    /// The shared secret used to sign the payload. Supports context objects.
+    pub secret: String,
+
+    /// The hash algorithm of the HMAC.
+    #[serde(default)]
+    pub algorithm: SigningAlgorithm,
+
+    /// The header the signature is sent in.
+    #[serde(default = "_default_signature_header")]
+    pub header: String,
+
+    /// The header the Unix timestamp of the request is sent in.
+    #[serde(default = "_default_timestamp_header")]
+    pub timestamp_header: String,
+
+    /// The content that is signed.
+    #[serde(default)]
+    pub canonical: CanonicalFormat,
+
+    /// The id of the key, sent in the `key_id_header` so that the receiver knows
+    /// which secret to verify the signature with.
+    #[serde(default)]
+    pub key_id: Option<String>,
+
+    /// The header the id of the key is sent in.
+    #[serde(default = "_default_key_id_header")]
+    pub key_id_header: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SigningAlgorithm {
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum CanonicalFormat {
+    /// Signs the Unix timestamp of the request and the body, joined by a `.`.
+    #[default]
+    TimestampBody,
+    /// Signs only the body.
+    Body,
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum WebhookAuth {
-    Basic { username: String, password: String },
-    Bearer { token: String },
+    Basic {
+        username: String,
+        password: String,
+    },
+    Bearer {
+        token: String,
+    },
+    OAuth2 {
+        oauth2: OAuth2Config,
+    },
+    ApiKey {
+        #[serde(rename = "apiKey")]
+        api_key: ApiKeyConfig,
+    },
+    ApiKeyQuery {
+        #[serde(rename = "apiKeyQuery")]
+        api_key_query: ApiKeyQueryConfig,
+    },
+    AwsSigV4 {
+        #[serde(rename = "awsSigV4")]
+        aws_sig_v4: AwsSigV4Config,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeyConfig {
+    /// The header the key is sent in.
+    #[serde(default = "_default_api_key_header")]
+    pub header: String,
+
+    /// The API key. Supports context objects.
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeyQueryConfig {
+    /// The query string parameter the key is sent in.
+    #[serde(default = "_default_api_key_param")]
+    pub param: String,
+
+    /// The API key. Supports context objects.
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AwsSigV4Config {
+    /// The AWS region of the target, like `us-east-1`.
+    pub region: String,
+
+    /// The name of the service the target belongs to, like `execute-api` for API
+    /// Gateway or `lambda` for Lambda function URLs.
+    pub service: String,
+
+    /// The access key id. Supports context objects. Defaults to the
+    /// `AWS_ACCESS_KEY_ID` environment variable.
+    #[serde(default)]
+    pub access_key_id: Option<String>,
+
+    /// The secret access key. Supports context objects. Defaults to the
+    /// `AWS_SECRET_ACCESS_KEY` environment variable.
+    #[serde(default)]
+    pub secret_access_key: Option<String>,
+
+    /// The session token of temporary credentials. Supports context objects.
+    /// Defaults to the `AWS_SESSION_TOKEN` environment variable.
+    #[serde(default)]
+    pub session_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OAuth2Config {
+    /// The URL of the token endpoint. Supports context objects.
+    pub token_url: String,
+
+    /// The client id. Supports context objects.
+    pub client_id: String,
+
+    /// The client secret. Supports context objects.
+    pub client_secret: String,
+
+    /// The scopes to request.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+
+    /// The audience to request the token for, if the provider needs one. Supports
+    /// context objects.
+    #[serde(default)]
+    pub audience: Option<String>,
+}
+
+fn _default_signature_header() -> String {
+    String::from(crate::signing::DEFAULT_SIGNATURE_HEADER)
+}
+
+fn _default_timestamp_header() -> String {
+    String::from(crate::signing::DEFAULT_TIMESTAMP_HEADER)
+}
+
+fn _default_key_id_header() -> String {
+    String::from(crate::signing::DEFAULT_KEY_ID_HEADER)
+}
+
+fn _default_api_key_header() -> String {
+    String::from("X-API-Key")
+}
+
+fn _default_api_key_param() -> String {
+    String::from("api_key")
 }
 
 fn _default_retries() -> u8 {
@@ -57,6 +707,38 @@ fn _default_timeout() -> u8 {
     10
 }
 
+fn _default_failure_threshold() -> u32 {
+    5
+}
+
+fn _default_cooldown() -> u64 {
+    60
+}
+
+fn _default_max_backlog() -> usize {
+    10000
+}
+
+fn _default_min_backoff() -> f64 {
+    1.0
+}
+
+fn _default_max_backoff() -> f64 {
+    60.0
+}
+
+fn _default_dedup_ttl() -> u64 {
+    600
+}
+
+fn _default_period() -> u64 {
+    60
+}
+
+fn _default_max_queue() -> usize {
+    100
+}
+
 fn _default_true() -> bool {
     true
 }
@@ -71,6 +753,86 @@ impl std::fmt::Debug for WebhookAuth {
                 write!(f, "Basic {{ username: ******, password: ****** }}")
             }
             WebhookAuth::Bearer { token: _ } => write!(f, "Bearer {{ token: ******* }}"),
+            WebhookAuth::OAuth2 { oauth2 } => write!(f, "OAuth2 {{ oauth2: {:?} }}", oauth2),
+            WebhookAuth::ApiKey { api_key } => {
+                write!(f, "ApiKey {{ header: {}, value: ****** }}", api_key.header)
+            }
+            WebhookAuth::ApiKeyQuery { api_key_query } => {
+                write!(
+                    f,
+                    "ApiKeyQuery {{ param: {}, value: ****** }}",
+                    api_key_query.param
+                )
+            }
+            WebhookAuth::AwsSigV4 { aws_sig_v4 } => {
+                write!(
+                    f,
+                    "AwsSigV4 {{ region: {}, service: {}, credentials: ****** }}",
+                    aws_sig_v4.region, aws_sig_v4.service
+                )
+            }
         }
     }
 }
+
+impl std::fmt::Debug for OAuth2Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OAuth2Config")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("client_secret", &"******")
+            .field("scopes", &self.scopes)
+            .field("audience", &self.audience)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for WebhookSigning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookSigning")
+            .field("secret", &"******")
+            .field("algorithm", &self.algorithm)
+            .field("header", &self.header)
+            .field("timestamp_header", &self.timestamp_header)
+            .field("canonical", &self.canonical)
+            .field("key_id", &self.key_id)
+            .field("key_id_header", &self.key_id_header)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for DeviceConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeviceConfig")
+            .field("altitude", &self.altitude)
+            .field("clock_offset", &self.clock_offset)
+            .field("secret", &self.secret.as_ref().map(|_| "******"))
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for VerificationConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VerificationConfig")
+            .field("required", &self.required)
+            .field("algorithm", &self.algorithm)
+            .field("header", &self.header)
+            .field("timestamp_header", &self.timestamp_header)
+            .field("key_id_header", &self.key_id_header)
+            .field("canonical", &self.canonical)
+            .field("tolerance", &self.tolerance)
+            .field("keys", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for ProxyConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProxyConfig")
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "******"))
+            .field("no_proxy", &self.no_proxy)
+            .finish()
+    }
+}
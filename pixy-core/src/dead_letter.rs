@@ -0,0 +1,222 @@
+//! Dead letters, the readings that could not be delivered to a target.
+//!
+//! When a target fails a delivery after all of its retries, the reading is sent to
+//! the dead letter destination along with why it failed. Dead letters stored in a
+//! file can later be replayed to the target they were meant for.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::backlog::StoredMessage;
+use crate::SensorMessage;
+
+/// Why a reading could not be delivered to a target.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// The name of the target that the reading could not be delivered to.
+    pub target: String,
+
+    /// The error of the last attempt.
+    pub error: String,
+
+    /// The number of requests that were made to the target.
+    pub attempts: u32,
+
+    /// When the delivery failed.
+    pub failed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    dead_letter: DeadLetter,
+    reading: StoredMessage,
+}
+
+/// The number of dead letters that were replayed.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReplaySummary {
+    /// The dead letters that were delivered to their target.
+    pub replayed: usize,
+
+    /// The dead letters that failed again, and were kept in the file.
+    pub failed: usize,
+}
+
+fn append(path: &Path, lines: &[String]) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+
+    Ok(())
+}
+
+/// The content of a dead letter file.
+#[derive(Debug, Default)]
+pub(crate) struct StoredDeadLetters {
+    /// The dead letters, along with the reading that could not be delivered.
+    pub(crate) dead_letters: Vec<(DeadLetter, SensorMessage)>,
+    /// The lines of the file that are not dead letters, which are kept as is.
+    pub(crate) unreadable: Vec<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct DeadLetterFile {
+    path: PathBuf,
+}
+
+impl DeadLetterFile {
+    pub(crate) fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path),
+        }
+    }
+
+    /// Appends a dead letter to the file.
+    pub(crate) fn push(&self, dead_letter: &DeadLetter, message: &SensorMessage) {
+        let entry = Entry {
+            dead_letter: dead_letter.clone(),
+            reading: StoredMessage::from(message),
+        };
+
+        let result = serde_json::to_string(&entry)
+            .map_err(std::io::Error::other)
+            .and_then(|line| append(&self.path, &[line]));
+
+        if let Err(e) = result {
+            warn!(path = ?self.path, error = %e, "Failed to store dead letter");
+        }
+    }
+
+    /// Takes the dead letters out of the file to replay them. The file is moved
+    /// aside first, so that dead letters stored in the meantime are not lost.
+    pub(crate) fn take(&self) -> Result<StoredDeadLetters, String> {
+        let replaying = self.path.with_extension("replaying");
+
+        if !self.path.exists() && !replaying.exists() {
+            return Ok(StoredDeadLetters::default());
+        }
+
+        // A previous replay may have been interrupted, in which case its dead
+        // letters are still waiting in the file moved aside
+        if !replaying.exists() {
+            std::fs::rename(&self.path, &replaying).map_err(|e| {
+                format!(
+                    "Failed to read dead letters from {}: {}",
+                    self.path.display(),
+                    e
+                )
+            })?;
+        }
+
+        let file = File::open(&replaying)
+            .map_err(|e| format!("Failed to read {}: {}", replaying.display(), e))?;
+
+        let mut stored = StoredDeadLetters::default();
+
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            match serde_json::from_str::<Entry>(&line) {
+                Ok(entry) => stored
+                    .dead_letters
+                    .push((entry.dead_letter, entry.reading.into())),
+                Err(_) if line.trim().is_empty() => {}
+                Err(_) => stored.unreadable.push(line),
+            }
+        }
+
+        Ok(stored)
+    }
+
+    /// Stores the dead letters that were not replayed back into the file, and
+    /// removes the file that was moved aside.
+    pub(crate) fn restore(
+        &self,
+        dead_letters: &[(DeadLetter, SensorMessage)],
+        unreadable: &[String],
+    ) -> Result<(), String> {
+        let lines: Vec<String> = dead_letters
+            .iter()
+            .filter_map(|(dead_letter, message)| {
+                serde_json::to_string(&Entry {
+                    dead_letter: dead_letter.clone(),
+                    reading: StoredMessage::from(message),
+                })
+                .ok()
+            })
+            .chain(unreadable.iter().cloned())
+            .collect();
+
+        if !lines.is_empty() {
+            append(&self.path, &lines)
+                .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))?;
+        }
+
+        let replaying = self.path.with_extension("replaying");
+
+        match std::fs::remove_file(&replaying) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(format!("Failed to remove {}: {}", replaying.display(), e))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_MESSAGE: &str = include_str!("../../example-configs/test-sensor.json");
+
+    #[test]
+    fn test_dead_letters_round_trip() {
+        let path = std::env::temp_dir().join(format!("pixy-dead-{}.jsonl", std::process::id()));
+        let file = DeadLetterFile::new(&path.to_string_lossy());
+
+        let _ = std::fs::remove_file(&path);
+
+        let message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+        let dead_letter = DeadLetter {
+            target: String::from("influx"),
+            error: String::from("HTTP status server error (500 Internal Server Error)"),
+            attempts: 4,
+            failed_at: Utc::now(),
+        };
+
+        file.push(&dead_letter, &message);
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|mut f| writeln!(f, "not json"))
+            .unwrap();
+
+        let stored = file.take().unwrap();
+
+        assert_eq!(stored.dead_letters.len(), 1);
+        assert_eq!(stored.dead_letters[0].0, dead_letter);
+        assert_eq!(stored.unreadable, vec![String::from("not json")]);
+        assert!(!path.exists());
+
+        // Dead letters stored while replaying are kept along with the failed ones
+        file.push(&dead_letter, &message);
+        file.restore(&stored.dead_letters, &stored.unreadable)
+            .unwrap();
+
+        let stored = file.take().unwrap();
+
+        assert_eq!(stored.dead_letters.len(), 2);
+        assert_eq!(stored.unreadable.len(), 1);
+
+        file.restore(&[], &[]).unwrap();
+
+        assert!(!path.exists());
+        assert!(!path.with_extension("replaying").exists());
+    }
+}
@@ -0,0 +1,240 @@
+//! Suppression of readings that were already received.
+//!
+//! The Enviro firmware retries an upload when it times out waiting for a response,
+//! so the same reading can arrive more than once. Readings are identified by the
+//! `uid` of the device and their `timestamp`, and are remembered for a configurable
+//! amount of time. The cache can optionally be persisted to a file so that it
+//! survives restarts.
+//!
+//! A reading is only remembered once it has been accepted, so an upload that was
+//! rejected, such as for an invalid signature, can be retried. Once accepted, a
+//! reading is remembered even if its deliveries fail, since the board is not told
+//! about them; those deliveries are retried by the targets instead.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::config::DeduplicationConfig;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    key: String,
+    seen_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    seen: HashMap<String, u64>,
+    /// The keys in the order they expire in, which is the order they were seen in
+    /// since every entry lives for the same TTL.
+    expiry: VecDeque<(u64, String)>,
+}
+
+impl Entries {
+    fn insert(&mut self, key: String, seen_at: u64) {
+        self.expiry.push_back((seen_at, key.clone()));
+        self.seen.insert(key, seen_at);
+    }
+
+    /// Forgets the entries that have expired, from the oldest until the first one
+    /// that has not.
+    fn prune(&mut self, ttl: u64, now: u64) {
+        while let Some((seen_at, _)) = self.expiry.front() {
+            if seen_at + ttl > now {
+                break;
+            }
+
+            let (seen_at, key) = self.expiry.pop_front().expect("expiry is not empty");
+
+            // The key may have been seen again since, if it expired in between
+            if self.seen.get(&key) == Some(&seen_at) {
+                self.seen.remove(&key);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct DedupCache {
+    ttl: u64,
+    path: Option<PathBuf>,
+    entries: Mutex<Entries>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl DedupCache {
+    pub(crate) fn new(config: &DeduplicationConfig) -> Self {
+        let path = config.persist.as_ref().map(PathBuf::from);
+
+        let cache = Self {
+            ttl: config.ttl,
+            path,
+            entries: Mutex::new(Entries::default()),
+        };
+
+        cache.load();
+
+        cache
+    }
+
+    /// Reads the persisted entries that have not expired, and rewrites the file
+    /// with only those entries so that it does not grow forever.
+    fn load(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let Ok(file) = File::open(path) else {
+            debug!(?path, "No deduplication cache to load");
+            return;
+        };
+
+        let now = now();
+        let mut entries = self.entries.lock().expect("dedup lock poisoned");
+
+        let mut loaded: Vec<Entry> = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str::<Entry>(&line).ok())
+            .filter(|entry| entry.seen_at + self.ttl > now)
+            .collect();
+
+        loaded.sort_by_key(|entry| entry.seen_at);
+
+        for entry in loaded {
+            entries.insert(entry.key, entry.seen_at);
+        }
+
+        let compacted = entries
+            .seen
+            .iter()
+            .filter_map(|(key, &seen_at)| {
+                serde_json::to_string(&Entry {
+                    key: key.clone(),
+                    seen_at,
+                })
+                .ok()
+            })
+            .map(|line| line + "\n")
+            .collect::<String>();
+
+        if let Err(e) = std::fs::write(path, compacted) {
+            warn!(?path, error = %e, "Failed to compact deduplication cache");
+        }
+    }
+
+    fn persist(&self, entry: &Entry) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| {
+                let line = serde_json::to_string(entry).map_err(std::io::Error::other)?;
+                writeln!(file, "{}", line)
+            });
+
+        if let Err(e) = result {
+            warn!(?path, error = %e, "Failed to persist deduplication cache entry");
+        }
+    }
+
+    /// Records that a reading was received, returning `false` if it had already
+    /// been received within the TTL.
+    pub(crate) fn insert(&self, uid: &str, timestamp: &str) -> bool {
+        let key = format!("{}/{}", uid, timestamp);
+        let now = now();
+
+        {
+            let mut entries = self.entries.lock().expect("dedup lock poisoned");
+
+            entries.prune(self.ttl, now);
+
+            if entries.seen.contains_key(&key) {
+                return false;
+            }
+
+            entries.insert(key.clone(), now);
+        }
+
+        self.persist(&Entry { key, seen_at: now });
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(persist: Option<String>) -> DeduplicationConfig {
+        DeduplicationConfig { ttl: 600, persist }
+    }
+
+    #[test]
+    fn test_duplicates_are_detected() {
+        let cache = DedupCache::new(&config(None));
+
+        assert!(cache.insert("device", "2023-07-25T14:30:13Z"));
+        assert!(!cache.insert("device", "2023-07-25T14:30:13Z"));
+        assert!(cache.insert("device", "2023-07-25T14:31:13Z"));
+        assert!(cache.insert("other", "2023-07-25T14:30:13Z"));
+    }
+
+    #[test]
+    fn test_entries_expire() {
+        let cache = DedupCache::new(&DeduplicationConfig {
+            ttl: 0,
+            persist: None,
+        });
+
+        assert!(cache.insert("device", "2023-07-25T14:30:13Z"));
+        assert!(cache.insert("device", "2023-07-25T14:30:13Z"));
+    }
+
+    #[test]
+    fn test_cache_is_persisted() {
+        let path = std::env::temp_dir().join(format!("pixy-dedup-{}.jsonl", std::process::id()));
+        let persist = Some(path.to_string_lossy().to_string());
+
+        let _ = std::fs::remove_file(&path);
+
+        let cache = DedupCache::new(&config(persist.clone()));
+        assert!(cache.insert("device", "2023-07-25T14:30:13Z"));
+
+        let reloaded = DedupCache::new(&config(persist));
+        assert!(!reloaded.insert("device", "2023-07-25T14:30:13Z"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_expired_entries_are_pruned() {
+        let mut entries = Entries::default();
+
+        entries.insert(String::from("a"), 100);
+        entries.insert(String::from("b"), 200);
+
+        entries.prune(600, 750);
+
+        assert!(!entries.seen.contains_key("a"));
+        assert!(entries.seen.contains_key("b"));
+        assert_eq!(entries.expiry.len(), 1);
+    }
+}
@@ -0,0 +1,174 @@
+//! Meteorological values derived from the raw sensor readings.
+//!
+//! These are the values that most users of the Enviro boards end up computing
+//! by hand from the temperature, humidity and pressure. They are calculated by
+//! the gateway for every reading and exposed to templates as `derived`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Readings;
+
+// Magnus formula coefficients (Sonntag, 1990), valid from -45°C to 60°C.
+const MAGNUS_A: f64 = 17.62;
+const MAGNUS_B: f64 = 243.12;
+
+/// Values derived from the temperature, humidity and pressure of a reading.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DerivedReadings {
+    /// The dew point in degrees Celsius.
+    pub dew_point: f32,
+
+    /// The absolute humidity in g/m³.
+    pub absolute_humidity: f32,
+
+    /// The heat index (apparent temperature) in degrees Celsius.
+    pub heat_index: f32,
+
+    /// The Canadian humidex, in degrees Celsius.
+    pub humidex: f32,
+
+    /// The pressure reduced to sea level in hPa. Only available when the
+    /// altitude of the device is configured.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub sea_level_pressure: Option<f32>,
+}
+
+impl DerivedReadings {
+    /// Computes the derived values for the given readings.
+    ///
+    /// ## Arguments
+    ///
+    /// * `readings` - The readings sent by the sensor.
+    /// * `altitude` - The altitude of the device in meters, if known.
+    pub fn compute(readings: &Readings, altitude: Option<f32>) -> Self {
+        let temperature = readings.temperature as f64;
+        let humidity = readings.humidity as f64;
+        let pressure = readings.pressure as f64;
+
+        let dew_point = dew_point(temperature, humidity);
+
+        Self {
+            dew_point: dew_point as f32,
+            absolute_humidity: absolute_humidity(temperature, humidity) as f32,
+            heat_index: heat_index(temperature, humidity) as f32,
+            humidex: humidex(temperature, dew_point) as f32,
+            sea_level_pressure: altitude
+                .map(|altitude| sea_level_pressure(pressure, temperature, altitude as f64) as f32),
+        }
+    }
+}
+
+fn dew_point(temperature: f64, humidity: f64) -> f64 {
+    // Clamp to avoid ln(0) when the sensor reports 0% humidity
+    let gamma =
+        (humidity.max(0.01) / 100.0).ln() + MAGNUS_A * temperature / (MAGNUS_B + temperature);
+
+    MAGNUS_B * gamma / (MAGNUS_A - gamma)
+}
+
+fn absolute_humidity(temperature: f64, humidity: f64) -> f64 {
+    let saturation_pressure = 6.112 * (MAGNUS_A * temperature / (MAGNUS_B + temperature)).exp();
+
+    saturation_pressure * humidity * 2.1674 / (273.15 + temperature)
+}
+
+fn heat_index(temperature: f64, humidity: f64) -> f64 {
+    // The NOAA heat index equation works in Fahrenheit
+    let t = temperature * 9.0 / 5.0 + 32.0;
+    let rh = humidity;
+
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+
+    let index = if (simple + t) / 2.0 < 80.0 {
+        simple
+    } else {
+        let mut index = -42.379 + 2.04901523 * t + 10.14333127 * rh
+            - 0.22475541 * t * rh
+            - 0.00683783 * t * t
+            - 0.05481717 * rh * rh
+            + 0.00122874 * t * t * rh
+            + 0.00085282 * t * rh * rh
+            - 0.00000199 * t * t * rh * rh;
+
+        if rh < 13.0 && (80.0..=112.0).contains(&t) {
+            index -= ((13.0 - rh) / 4.0) * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+        } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+            index += ((rh - 85.0) / 10.0) * ((87.0 - t) / 5.0);
+        }
+
+        index
+    };
+
+    (index - 32.0) * 5.0 / 9.0
+}
+
+fn humidex(temperature: f64, dew_point: f64) -> f64 {
+    let vapour_pressure = 6.11 * (5417.7530 * (1.0 / 273.16 - 1.0 / (273.15 + dew_point))).exp();
+
+    temperature + 0.5555 * (vapour_pressure - 10.0)
+}
+
+fn sea_level_pressure(pressure: f64, temperature: f64, altitude: f64) -> f64 {
+    let lapse = 0.0065 * altitude;
+
+    pressure * (1.0 - lapse / (temperature + lapse + 273.15)).powf(-5.257)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn readings(temperature: f32, humidity: f32, pressure: f32) -> Readings {
+        Readings {
+            temperature,
+            pressure,
+            humidity,
+            color_temperature: None,
+            gas_resistance: None,
+            aqi: None,
+            luminance: None,
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.1,
+            "expected {} to be close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_derived_values() {
+        let derived = DerivedReadings::compute(&readings(25.0, 60.0, 1000.0), None);
+
+        assert_close(derived.dew_point, 16.7);
+        assert_close(derived.absolute_humidity, 13.8);
+        assert_close(derived.heat_index, 25.1);
+        assert_close(derived.humidex, 30.1);
+        assert!(derived.sea_level_pressure.is_none());
+    }
+
+    #[test]
+    fn test_heat_index_in_hot_weather() {
+        let derived = DerivedReadings::compute(&readings(32.0, 70.0, 1000.0), None);
+
+        assert_close(derived.heat_index, 40.4);
+    }
+
+    #[test]
+    fn test_sea_level_pressure_with_altitude() {
+        let derived = DerivedReadings::compute(&readings(15.0, 50.0, 1000.0), Some(100.0));
+
+        assert_close(derived.sea_level_pressure.unwrap(), 1011.9);
+    }
+
+    #[test]
+    fn test_zero_humidity_is_finite() {
+        let derived = DerivedReadings::compute(&readings(20.0, 0.0, 1000.0), None);
+
+        assert!(derived.dew_point.is_finite());
+        assert!(derived.humidex.is_finite());
+    }
+}
@@ -1,3 +1,6 @@
+mod aws;
+mod oauth2;
+mod retry;
 mod webhook;
 
 pub use webhook::WebhookHandler;
@@ -0,0 +1,301 @@
+//! AWS Signature Version 4 signing, for targets such as API Gateway or Lambda
+//! function URLs that use IAM authentication.
+//!
+//! See <https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_sigv-create-signed-request.html>
+//! for a description of the signing process.
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use minijinja::{value::Value, Environment};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+
+use crate::config::AwsSigV4Config;
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+/// The credentials that requests are signed with.
+pub(crate) struct Credentials {
+    pub(crate) access_key_id: String,
+    pub(crate) secret_access_key: String,
+    pub(crate) session_token: Option<String>,
+}
+
+/// Renders a setting, falling back to an environment variable when it is not set.
+fn setting(
+    env: &Environment,
+    template: Option<&String>,
+    variable: &str,
+    context: &Value,
+) -> Result<Option<String>, String> {
+    match template {
+        Some(template) => env
+            .render_str(template, context)
+            .map(Some)
+            .map_err(|e| e.to_string()),
+        None => Ok(std::env::var(variable).ok()),
+    }
+}
+
+impl Credentials {
+    /// Reads the credentials from the settings of a target, or from the standard
+    /// AWS environment variables.
+    pub(crate) fn resolve(
+        config: &AwsSigV4Config,
+        env: &Environment,
+        context: &Value,
+    ) -> Result<Self, String> {
+        let access_key_id = setting(
+            env,
+            config.access_key_id.as_ref(),
+            "AWS_ACCESS_KEY_ID",
+            context,
+        )?
+        .ok_or_else(|| String::from("No AWS access key id configured"))?;
+
+        let secret_access_key = setting(
+            env,
+            config.secret_access_key.as_ref(),
+            "AWS_SECRET_ACCESS_KEY",
+            context,
+        )?
+        .ok_or_else(|| String::from("No AWS secret access key configured"))?;
+
+        let session_token = setting(
+            env,
+            config.session_token.as_ref(),
+            "AWS_SESSION_TOKEN",
+            context,
+        )?;
+
+        Ok(Self {
+            access_key_id,
+            secret_access_key,
+            session_token,
+        })
+    }
+}
+
+/// The region and service that a request is sent to.
+pub(crate) struct Scope<'a> {
+    pub(crate) region: &'a str,
+    pub(crate) service: &'a str,
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything except the unreserved characters, as required by AWS.
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            b'/' if !encode_slash => String::from("/"),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn canonical_query(url: &Url) -> String {
+    let mut pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(key, value)| (uri_encode(&key, true), uri_encode(&value, true)))
+        .collect();
+
+    pairs.sort();
+
+    pairs
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// The value of the `Host` header that will be sent for a URL.
+pub(crate) fn host(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+/// Signs a request, returning the headers to add to it.
+///
+/// ## Arguments
+///
+/// * `credentials` - The credentials to sign the request with.
+/// * `scope` - The region and service the request is sent to.
+/// * `method` - The HTTP method of the request.
+/// * `url` - The URL of the request.
+/// * `headers` - The headers of the request to sign, including `host`.
+/// * `body` - The body of the request.
+/// * `now` - The time of the request.
+pub(crate) fn sign(
+    credentials: &Credentials,
+    scope: &Scope,
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    now: DateTime<Utc>,
+) -> Vec<(&'static str, String)> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+
+    let mut added = vec![("x-amz-date", amz_date.clone())];
+
+    if let Some(token) = &credentials.session_token {
+        added.push(("x-amz-security-token", token.clone()));
+    }
+
+    let mut signed: Vec<(String, String)> = headers
+        .iter()
+        .map(|(name, value)| (name.to_lowercase(), value.trim().to_string()))
+        .chain(
+            added
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone())),
+        )
+        .collect();
+
+    signed.sort();
+
+    let canonical_headers: String = signed
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+
+    let signed_headers = signed
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let path = match url.path() {
+        "" => "/",
+        path => path,
+    };
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        uri_encode(path, false),
+        canonical_query(url),
+        canonical_headers,
+        signed_headers,
+        hex::encode(Sha256::digest(body))
+    );
+
+    let credential_scope = format!("{}/{}/{}/aws4_request", date, scope.region, scope.service);
+
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        ALGORITHM,
+        amz_date,
+        credential_scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = [date.as_str(), scope.region, scope.service, "aws4_request"]
+        .iter()
+        .fold(
+            format!("AWS4{}", credentials.secret_access_key).into_bytes(),
+            |key, part| hmac_sha256(&key, part.as_bytes()),
+        );
+
+    let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+    added.push((
+        "authorization",
+        format!(
+            "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+            ALGORITHM, credentials.access_key_id, credential_scope, signed_headers, signature
+        ),
+    ));
+
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(session_token: Option<&str>) -> Credentials {
+        Credentials {
+            access_key_id: String::from("AKIDEXAMPLE"),
+            secret_access_key: String::from("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"),
+            session_token: session_token.map(String::from),
+        }
+    }
+
+    fn sign_example(credentials: &Credentials) -> Vec<(&'static str, String)> {
+        let url =
+            Url::parse("https://iam.amazonaws.com/?Version=2010-05-08&Action=ListUsers").unwrap();
+        let now = DateTime::parse_from_rfc3339("2015-08-30T12:36:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        sign(
+            credentials,
+            &Scope {
+                region: "us-east-1",
+                service: "iam",
+            },
+            "GET",
+            &url,
+            &[
+                ("Host", &host(&url)),
+                (
+                    "Content-Type",
+                    "application/x-www-form-urlencoded; charset=utf-8",
+                ),
+            ],
+            b"",
+            now,
+        )
+    }
+
+    fn header<'a>(headers: &'a [(&'static str, String)], name: &str) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|(header, _)| *header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The expected signature is the example from the AWS documentation on
+    // creating signed requests
+    #[test]
+    fn test_sign_request() {
+        let headers = sign_example(&credentials(None));
+
+        assert_eq!(header(&headers, "x-amz-date"), Some("20150830T123600Z"));
+        assert_eq!(
+            header(&headers, "authorization"),
+            Some("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, SignedHeaders=content-type;host;x-amz-date, Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7")
+        );
+    }
+
+    #[test]
+    fn test_session_token_is_signed() {
+        let headers = sign_example(&credentials(Some("token")));
+
+        assert_eq!(header(&headers, "x-amz-security-token"), Some("token"));
+        assert!(header(&headers, "authorization")
+            .unwrap()
+            .contains("SignedHeaders=content-type;host;x-amz-date;x-amz-security-token,"));
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!(uri_encode("/a b/c~d", false), "/a%20b/c~d");
+        assert_eq!(uri_encode("a/b=c", true), "a%2Fb%3Dc");
+    }
+}
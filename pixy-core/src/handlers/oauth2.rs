@@ -0,0 +1,165 @@
+//! Access tokens for targets using the OAuth2 client credentials grant.
+//!
+//! Tokens are fetched from the token endpoint the first time they are needed, and
+//! reused until shortly before they expire. A token can also be discarded early,
+//! such as when the target responds with `401 Unauthorized`.
+
+use std::time::{Duration, Instant};
+
+use minijinja::{value::Value, Environment};
+use reqwest_middleware::ClientWithMiddleware;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use tracing::{debug, error};
+
+use crate::config::OAuth2Config;
+
+/// The longest time before its expiry that a token is refreshed.
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+struct CachedToken {
+    access_token: String,
+    /// When the token should be refreshed. Tokens without an expiry are kept
+    /// until they are rejected.
+    refresh_at: Option<Instant>,
+}
+
+#[derive(Default)]
+pub(crate) struct TokenCache {
+    token: Mutex<Option<CachedToken>>,
+}
+
+impl std::fmt::Debug for TokenCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenCache").finish_non_exhaustive()
+    }
+}
+
+/// The time after which a token that expires after `expires_in` seconds is
+/// refreshed. Short-lived tokens are refreshed after 90% of their lifetime.
+fn refresh_at(now: Instant, expires_in: Option<u64>) -> Option<Instant> {
+    expires_in.map(|seconds| {
+        let lifetime = Duration::from_secs(seconds);
+
+        now + lifetime - REFRESH_MARGIN.min(lifetime / 10)
+    })
+}
+
+fn render(env: &Environment, template: &str, context: &Value) -> Result<String, String> {
+    env.render_str(template, context).map_err(|e| {
+        error!("Error rendering OAuth2 settings: {}", e);
+        e.to_string()
+    })
+}
+
+impl TokenCache {
+    /// Returns an access token, fetching a new one if there is no valid token.
+    pub(crate) async fn token(
+        &self,
+        client: &ClientWithMiddleware,
+        config: &OAuth2Config,
+        context: &Value,
+    ) -> Result<String, String> {
+        let mut cached = self.token.lock().await;
+
+        if let Some(token) = cached.as_ref() {
+            if token.refresh_at.is_none_or(|at| Instant::now() < at) {
+                return Ok(token.access_token.clone());
+            }
+        }
+
+        let token = fetch(client, config, context).await?;
+        let access_token = token.access_token.clone();
+
+        *cached = Some(token);
+
+        Ok(access_token)
+    }
+
+    /// Discards the cached token, so that the next request fetches a new one.
+    pub(crate) async fn invalidate(&self) {
+        *self.token.lock().await = None;
+    }
+}
+
+async fn fetch(
+    client: &ClientWithMiddleware,
+    config: &OAuth2Config,
+    context: &Value,
+) -> Result<CachedToken, String> {
+    let env = Environment::new();
+
+    let token_url = render(&env, &config.token_url, context)?;
+
+    let mut form = vec![
+        ("grant_type", String::from("client_credentials")),
+        ("client_id", render(&env, &config.client_id, context)?),
+        (
+            "client_secret",
+            render(&env, &config.client_secret, context)?,
+        ),
+    ];
+
+    if !config.scopes.is_empty() {
+        form.push(("scope", config.scopes.join(" ")));
+    }
+
+    if let Some(audience) = &config.audience {
+        form.push(("audience", render(&env, audience, context)?));
+    }
+
+    debug!(token_url, "Fetching OAuth2 access token");
+
+    let requested_at = Instant::now();
+
+    let response = client
+        .post(&token_url)
+        .form(&form)
+        .send()
+        .await
+        .and_then(|r| {
+            r.error_for_status()
+                .map_err(reqwest_middleware::Error::from)
+        })
+        .map_err(|e| {
+            error!(error = ?e, "Failed to fetch OAuth2 access token");
+            e.to_string()
+        })?;
+
+    let token: TokenResponse = response.json().await.map_err(|e| {
+        error!(error = ?e, "Invalid OAuth2 token response");
+        e.to_string()
+    })?;
+
+    Ok(CachedToken {
+        access_token: token.access_token,
+        refresh_at: refresh_at(requested_at, token.expires_in),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_refreshed_before_expiry() {
+        let now = Instant::now();
+
+        assert_eq!(
+            refresh_at(now, Some(3600)),
+            Some(now + Duration::from_secs(3540))
+        );
+        assert_eq!(
+            refresh_at(now, Some(300)),
+            Some(now + Duration::from_secs(270))
+        );
+        assert_eq!(refresh_at(now, None), None);
+    }
+}
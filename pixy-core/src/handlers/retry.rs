@@ -0,0 +1,239 @@
+//! The policy deciding whether and when a failed request to a webhook is retried.
+//!
+//! Requests are retried with an exponential backoff, until either the number of
+//! retries or the deadline of the target is reached. When a target answers with a
+//! `Retry-After` header, it is waited for instead of the backoff, up to the longest
+//! backoff.
+
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use reqwest_retry::{
+    default_on_request_failure, policies::ExponentialBackoff, Jitter, RetryDecision,
+    RetryPolicy as _, Retryable,
+};
+
+use crate::config::{RetryConfig, RetryJitter};
+
+impl From<RetryJitter> for Jitter {
+    fn from(jitter: RetryJitter) -> Self {
+        match jitter {
+            RetryJitter::None => Jitter::None,
+            RetryJitter::Full => Jitter::Full,
+            RetryJitter::Bounded => Jitter::Bounded,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RetryPolicy {
+    backoff: ExponentialBackoff,
+    max_backoff: Duration,
+    deadline: Option<Duration>,
+    status_codes: Option<Vec<u16>>,
+    retry_timeouts: bool,
+}
+
+impl RetryPolicy {
+    pub(crate) fn new(retries: u8, config: &RetryConfig) -> Self {
+        let min_backoff = Duration::from_secs_f64(config.min_backoff.max(0.0));
+        let max_backoff = Duration::from_secs_f64(config.max_backoff.max(0.0)).max(min_backoff);
+
+        Self {
+            backoff: ExponentialBackoff::builder()
+                .retry_bounds(min_backoff, max_backoff)
+                .jitter(config.jitter.into())
+                .build_with_max_retries(retries as u32),
+            max_backoff,
+            deadline: config.deadline.map(Duration::from_secs),
+            status_codes: config.status_codes.clone(),
+            retry_timeouts: config.retry_timeouts,
+        }
+    }
+
+    /// Whether the result of an attempt is a failure that can be retried.
+    pub(crate) fn is_retryable(
+        &self,
+        result: &Result<Response, reqwest_middleware::Error>,
+    ) -> bool {
+        match result {
+            Ok(response) => self.is_retryable_status(response.status()),
+            Err(reqwest_middleware::Error::Reqwest(e)) if e.is_timeout() => self.retry_timeouts,
+            Err(e) => matches!(default_on_request_failure(e), Some(Retryable::Transient)),
+        }
+    }
+
+    fn is_retryable_status(&self, status: StatusCode) -> bool {
+        if status.is_success() {
+            return false;
+        }
+
+        match &self.status_codes {
+            Some(status_codes) => status_codes.contains(&status.as_u16()),
+            // The same status codes as the default policy of reqwest-retry
+            None => {
+                status.is_server_error()
+                    || status == StatusCode::REQUEST_TIMEOUT
+                    || status == StatusCode::TOO_MANY_REQUESTS
+            }
+        }
+    }
+
+    /// How long to wait before the next attempt, or `None` if the request should
+    /// not be retried anymore.
+    ///
+    /// ## Arguments
+    ///
+    /// * `past_retries` - The number of retries that were already made.
+    /// * `retry_after` - The delay requested by the target, if any.
+    /// * `elapsed` - The time since the first attempt.
+    pub(crate) fn delay(
+        &self,
+        past_retries: u32,
+        retry_after: Option<Duration>,
+        elapsed: Duration,
+    ) -> Option<Duration> {
+        // The start time is only used by policies with a total duration, which the
+        // deadline replaces
+        let RetryDecision::Retry { execute_after } =
+            self.backoff.should_retry(SystemTime::now(), past_retries)
+        else {
+            return None;
+        };
+
+        // A target cannot hold the delivery for longer than the longest backoff
+        let delay = match retry_after {
+            Some(retry_after) => retry_after.min(self.max_backoff),
+            None => execute_after
+                .duration_since(SystemTime::now())
+                .unwrap_or_default(),
+        };
+
+        match self.deadline {
+            Some(deadline) if elapsed + delay > deadline => None,
+            _ => Some(delay),
+        }
+    }
+}
+
+/// Reads the delay requested by the `Retry-After` header of a response, which is
+/// either a number of seconds or an HTTP date.
+pub(crate) fn retry_after(response: &Response, now: DateTime<Utc>) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    parse_retry_after(value, now)
+}
+
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(retries: u8, config: &str) -> RetryPolicy {
+        RetryPolicy::new(retries, &serde_yaml::from_str(config).unwrap())
+    }
+
+    #[test]
+    fn test_backoff_is_exponential_and_bounded() {
+        let policy = policy(5, "{ minBackoff: 1, maxBackoff: 5, jitter: none }");
+
+        let delays: Vec<_> = (0..6)
+            .map(|retries| policy.delay(retries, None, Duration::ZERO))
+            .collect();
+
+        // The delays are computed from the current time, so allow for the time
+        // passing while the test runs
+        for (delay, expected) in delays.iter().zip([1, 2, 4, 5, 5]) {
+            let delay = delay.unwrap();
+
+            assert!(delay <= Duration::from_secs(expected));
+            assert!(delay > Duration::from_secs(expected) - Duration::from_millis(100));
+        }
+
+        assert_eq!(delays[5], None);
+    }
+
+    #[test]
+    fn test_deadline_stops_retries() {
+        let policy = policy(10, "{ minBackoff: 1, jitter: none, deadline: 10 }");
+
+        assert!(policy.delay(0, None, Duration::from_secs(5)).is_some());
+        assert_eq!(policy.delay(0, None, Duration::from_secs(10)), None);
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(6)), Duration::from_secs(5)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_retry_after_replaces_backoff() {
+        let policy = policy(3, "{ minBackoff: 1, jitter: none }");
+
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(30)), Duration::ZERO),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn test_retry_after_is_bounded() {
+        let policy = policy(3, "{ minBackoff: 1, maxBackoff: 120, jitter: none }");
+
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(86400)), Duration::ZERO),
+            Some(Duration::from_secs(120))
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:29:30 GMT", now),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn test_retryable_status_codes() {
+        let policy = policy(3, "{}");
+
+        assert!(policy.is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(policy.is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(policy.is_retryable_status(StatusCode::NOT_IMPLEMENTED));
+        assert!(policy.is_retryable_status(StatusCode::INSUFFICIENT_STORAGE));
+        assert!(!policy.is_retryable_status(StatusCode::BAD_REQUEST));
+        assert!(!policy.is_retryable_status(StatusCode::NOT_FOUND));
+
+        let policy = self::policy(3, "{ statusCodes: [400] }");
+
+        assert!(policy.is_retryable_status(StatusCode::BAD_REQUEST));
+        assert!(!policy.is_retryable_status(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+}
@@ -1,11 +1,22 @@
-use crate::{clients, SensorHandler, SensorMessage};
+use super::aws::{self, Credentials, Scope};
+use super::oauth2::TokenCache;
+use super::retry::{self, RetryPolicy};
+use crate::signing::sign;
+use crate::{clients, DeliveryError, SensorHandler, SensorMessage};
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
-use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
-use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
-use tracing::{debug, error, info, instrument};
+use chrono::Utc;
+use opentelemetry::{global, propagation::TextMapPropagator};
+use opentelemetry_http::HeaderInjector;
+use reqwest::{
+    header::{HeaderMap, CONTENT_TYPE},
+    StatusCode, Url,
+};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, RequestBuilder};
+use tracing::{debug, error, field, info, info_span, instrument, warn, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use minijinja::{value::Value, Environment};
 
@@ -17,6 +28,8 @@ pub struct WebhookHandler {
     enabled: bool,
     config: WebhookTargetProperties,
     client: ClientWithMiddleware,
+    tokens: TokenCache,
+    retry: RetryPolicy,
 }
 
 impl WebhookHandler {
@@ -35,11 +48,19 @@ impl WebhookHandler {
     /// let target = Target {
     ///    name: "test".to_string(),
     ///    enabled: true,
+    ///    include_derived: false,
+    ///    aggregate: None,
+    ///    rate_limit: None,
+    ///    circuit_breaker: None,
     ///    properties: Webhook(WebhookTargetProperties {
     ///       url: "https://example.com".to_string(),
     ///       retries: 3,
     ///       timeout: 5,
+    ///       retry: None,
     ///       auth: None,
+    ///       signing: None,
+    ///       tls: None,
+    ///       proxy: None,
     ///   }),
     /// };
     ///
@@ -54,23 +75,258 @@ impl WebhookHandler {
             panic!("Invalid target properties for WebhookHandler");
         };
 
-        let retries: u32 = properties.retries as u32;
+        let retry = RetryPolicy::new(
+            properties.retries,
+            &properties.retry.clone().unwrap_or_default(),
+        );
 
-        let retry_policy = ExponentialBackoff::builder().build_with_max_retries(retries);
-
-        let middleware_client = ClientBuilder::new(client)
-            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-            .build();
+        let middleware_client = ClientBuilder::new(client).build();
 
         Self {
             name: target_config.name,
             enabled: target_config.enabled,
             config: properties,
             client: middleware_client,
+            tokens: TokenCache::default(),
+            retry,
+        }
+    }
+
+    /// Builds the request sending a body to the webhook, signing and authenticating it.
+    ///
+    /// The request is built again for every attempt, so that the timestamps of the
+    /// signatures stay current when requests are retried.
+    async fn request(&self, body: &[u8], context: &Value) -> Result<RequestBuilder, String> {
+        let request = self
+            .client
+            .post(&self.config.url)
+            .timeout(Duration::from_secs(self.config.timeout as u64))
+            .header(CONTENT_TYPE, "application/json");
+
+        let env = Environment::new();
+
+        let request = if let Some(signing) = &self.config.signing {
+            let secret = env.render_str(&signing.secret, context).map_err(|e| {
+                error!("Error rendering signing secret: {}", e);
+                e.to_string()
+            })?;
+
+            let timestamp = Utc::now().timestamp();
+            let signature = sign(
+                signing.algorithm,
+                signing.canonical,
+                secret.as_bytes(),
+                timestamp,
+                body,
+            );
+
+            let request = request
+                .header(&signing.timestamp_header, timestamp.to_string())
+                .header(&signing.header, signature);
+
+            match &signing.key_id {
+                Some(key_id) => request.header(&signing.key_id_header, key_id),
+                None => request,
+            }
+        } else {
+            request
+        };
+
+        let request = request.body(body.to_vec());
+
+        let request = if let Some(auth) = &self.config.auth {
+            match auth {
+                WebhookAuth::Basic { username, password } => {
+                    let username = env.render_str(username, context).map_err(|e| {
+                        error!("Error rendering username: {}", e);
+                        e.to_string()
+                    })?;
+                    let password = env.render_str(password, context).map_err(|e| {
+                        error!("Error rendering password: {}", e);
+                        e.to_string()
+                    })?;
+                    request.basic_auth(username, Some(password))
+                }
+                WebhookAuth::Bearer { token } => {
+                    request.bearer_auth(env.render_str(token, context).map_err(|e| {
+                        error!("Error rendering token: {}", e);
+                        e.to_string()
+                    })?)
+                }
+                WebhookAuth::OAuth2 { oauth2 } => {
+                    request.bearer_auth(self.tokens.token(&self.client, oauth2, context).await?)
+                }
+                WebhookAuth::ApiKey { api_key } => {
+                    let value = env.render_str(&api_key.value, context).map_err(|e| {
+                        error!("Error rendering API key: {}", e);
+                        e.to_string()
+                    })?;
+                    request.header(&api_key.header, value)
+                }
+                WebhookAuth::ApiKeyQuery { api_key_query } => {
+                    let value = env.render_str(&api_key_query.value, context).map_err(|e| {
+                        error!("Error rendering API key: {}", e);
+                        e.to_string()
+                    })?;
+                    request.query(&[(&api_key_query.param, value)])
+                }
+                WebhookAuth::AwsSigV4 { aws_sig_v4 } => {
+                    let credentials =
+                        Credentials::resolve(aws_sig_v4, &env, context).map_err(|e| {
+                            error!("Error reading AWS credentials: {}", e);
+                            e
+                        })?;
+
+                    let url = Url::parse(&self.config.url).map_err(|e| e.to_string())?;
+
+                    let scope = Scope {
+                        region: &aws_sig_v4.region,
+                        service: &aws_sig_v4.service,
+                    };
+
+                    aws::sign(
+                        &credentials,
+                        &scope,
+                        "POST",
+                        &url,
+                        &[
+                            ("host", &aws::host(&url)),
+                            ("content-type", "application/json"),
+                        ],
+                        body,
+                        Utc::now(),
+                    )
+                    .into_iter()
+                    .fold(request, |request, (name, value)| {
+                        request.header(name, value)
+                    })
+                }
+            }
+        } else {
+            request
+        };
+
+        Ok(request)
+    }
+
+    /// Sends a body to the webhook, retrying failed requests as set by the retry
+    /// policy of the target.
+    async fn send(&self, body: &[u8], context: &Value) -> Result<reqwest::Response, DeliveryError> {
+        let started = Instant::now();
+        let mut attempts = 0;
+        let mut retries = 0;
+        let mut reauthenticated = false;
+
+        loop {
+            // Each attempt has its own span, so that retries show up in traces
+            let span = info_span!(
+                "delivery_attempt",
+                handler = %self.name,
+                attempt = attempts + 1,
+                http.response.status_code = field::Empty,
+            );
+
+            let request = self
+                .request(body, context)
+                .instrument(span.clone())
+                .await
+                .map_err(|message| DeliveryError { message, attempts })?;
+
+            attempts += 1;
+
+            let result = request
+                .headers(trace_headers(&span))
+                .send()
+                .instrument(span.clone())
+                .await
+                .map_err(without_url);
+
+            if let Ok(response) = &result {
+                span.record("http.response.status_code", response.status().as_u16());
+            }
+
+            // The access token may have been revoked or expired early, so fetch a new
+            // one and try again once
+            if let (Some(WebhookAuth::OAuth2 { .. }), Ok(response)) = (&self.config.auth, &result) {
+                if response.status() == StatusCode::UNAUTHORIZED && !reauthenticated {
+                    info!("Access token was rejected, fetching a new one");
+                    self.tokens.invalidate().await;
+                    reauthenticated = true;
+                    continue;
+                }
+            }
+
+            let delay = if self.retry.is_retryable(&result) {
+                let retry_after = result
+                    .as_ref()
+                    .ok()
+                    .and_then(|response| retry::retry_after(response, Utc::now()));
+
+                self.retry.delay(retries, retry_after, started.elapsed())
+            } else {
+                None
+            };
+
+            let Some(delay) = delay else {
+                return result
+                    .and_then(|r| {
+                        r.error_for_status()
+                            .map_err(|e| reqwest_middleware::Error::from(e.without_url()))
+                    })
+                    .map_err(|e| {
+                        error!(error = ?e, attempts, "Failed to send reading data");
+                        DeliveryError {
+                            message: e.to_string(),
+                            attempts,
+                        }
+                    });
+            };
+
+            retries += 1;
+
+            match &result {
+                Ok(response) => warn!(
+                    response_status = response.status().as_u16(),
+                    "Retry attempt #{}. Sleeping {:?} before the next attempt", retries, delay
+                ),
+                Err(e) => warn!(
+                    error = %e,
+                    "Retry attempt #{}. Sleeping {:?} before the next attempt", retries, delay
+                ),
+            }
+
+            tokio::time::sleep(delay).await;
         }
     }
 }
 
+/// Removes the URL from the error of a request. The URL can carry secrets, such as
+/// an API key sent in the query string, and errors end up in the logs and in the
+/// status of the target.
+fn without_url(error: reqwest_middleware::Error) -> reqwest_middleware::Error {
+    match error {
+        reqwest_middleware::Error::Reqwest(e) => {
+            reqwest_middleware::Error::Reqwest(e.without_url())
+        }
+        e => e,
+    }
+}
+
+/// The headers propagating the trace of a span to the target, such as
+/// `traceparent`. They are empty unless a propagator is installed.
+fn trace_headers(span: &Span) -> HeaderMap {
+    global::get_text_map_propagator(|propagator| inject_trace_context(propagator, span))
+}
+
+/// The headers propagating the trace of a span with the given propagator.
+fn inject_trace_context(propagator: &dyn TextMapPropagator, span: &Span) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    propagator.inject_context(&span.context(), &mut HeaderInjector(&mut headers));
+
+    headers
+}
+
 impl From<Target> for WebhookHandler {
     /// Creates a new WebhookHandler given a target configuration.
     ///
@@ -86,11 +342,19 @@ impl From<Target> for WebhookHandler {
     /// let target = Target {
     ///    name: "test".to_string(),
     ///    enabled: true,
+    ///    include_derived: false,
+    ///    aggregate: None,
+    ///    rate_limit: None,
+    ///    circuit_breaker: None,
     ///    properties: Webhook(WebhookTargetProperties {
     ///       url: "https://example.com".to_string(),
     ///       retries: 3,
     ///       timeout: 5,
+    ///       retry: None,
     ///       auth: None,
+    ///       signing: None,
+    ///       tls: None,
+    ///       proxy: None,
     ///   }),
     /// };
     ///
@@ -106,64 +370,29 @@ impl From<Target> for WebhookHandler {
 
 #[async_trait]
 impl SensorHandler for WebhookHandler {
-    #[instrument]
-    async fn handle_reading(&self, reading: &SensorMessage, context: &Value) -> Result<(), String> {
+    #[instrument(skip_all, fields(handler = %self.name))]
+    async fn handle_reading(
+        &self,
+        reading: &SensorMessage,
+        context: &Value,
+    ) -> Result<(), DeliveryError> {
         info!(config = ?self.config, "Sending reading data to {}", &self.config.url);
-        let request = self
-            .client
-            .post(&self.config.url)
-            .timeout(Duration::from_secs(self.config.timeout as u64))
-            .json(reading);
 
-        let env = Environment::new();
+        let body = serde_json::to_vec(reading).map_err(|e| {
+            error!("Error serializing reading: {}", e);
+            DeliveryError::from(e.to_string())
+        })?;
 
-        let request = if let Some(auth) = &self.config.auth {
-            match auth {
-                WebhookAuth::Basic { username, password } => {
-                    let username = env.render_str(username, context).map_err(|e| {
-                        tracing::error!("Error rendering username: {}", e);
-                        e.to_string()
-                    })?;
-                    let password = env.render_str(password, context).map_err(|e| {
-                        tracing::error!("Error rendering password: {}", e);
-                        e.to_string()
-                    })?;
-                    request.basic_auth(username, Some(password))
-                }
-                WebhookAuth::Bearer { token } => {
-                    request.bearer_auth(env.render_str(token, context).map_err(|e| {
-                        tracing::error!("Error rendering token: {}", e);
-                        e.to_string()
-                    })?)
-                }
-            }
-        } else {
-            request
-        };
+        let response = self.send(&body, context).await?;
 
-        let response = request
-            .send()
-            .await
-            .and_then(|r| match r.error_for_status() {
-                Ok(res) => Ok(res),
-                Err(e) => Err(reqwest_middleware::Error::from(e)),
-            })
-            .map(|r| {
-                debug!(response = ?r, "Successfully sent reading data");
-                info!(
-                    response_status = r.status().as_u16(),
-                    target_url = %self.config.url,
-                );
-            })
-            .map_err(|e| {
-                error!(error = ?e, "Failed to send reading data");
-                e.to_string()
-            });
+        // The URL of the response is not logged, as it can carry an API key
+        debug!(headers = ?response.headers(), "Successfully sent reading data");
+        info!(
+            response_status = response.status().as_u16(),
+            target_url = %self.config.url,
+        );
 
-        match response {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e.to_string()),
-        }
+        Ok(())
     }
 
     fn get_name(&self) -> &str {
@@ -173,11 +402,16 @@ impl SensorHandler for WebhookHandler {
     fn is_enabled(&self) -> bool {
         self.enabled
     }
+
+    fn describe(&self) -> String {
+        format!("{:?}", self.config)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{CanonicalFormat, OAuth2Config, WebhookSigning};
     use httpmock::{Method::POST, MockServer};
     use minijinja::context;
 
@@ -188,7 +422,11 @@ mod tests {
             url: "http://localhost:8080".to_string(),
             retries: 3,
             timeout: 10,
+            retry: None,
             auth: None,
+            signing: None,
+            tls: None,
+            proxy: None,
         }
     }
 
@@ -197,6 +435,10 @@ mod tests {
         let target = Target {
             name: "test".to_string(),
             enabled: true,
+            include_derived: false,
+            aggregate: None,
+            rate_limit: None,
+            circuit_breaker: None,
             properties: Webhook(default_properties()),
         };
 
@@ -243,6 +485,10 @@ mod tests {
         let target = Target {
             name: "test".to_string(),
             enabled: true,
+            include_derived: false,
+            aggregate: None,
+            rate_limit: None,
+            circuit_breaker: None,
             properties: Webhook(properties),
         };
 
@@ -291,6 +537,10 @@ mod tests {
         let target = Target {
             name: "test".to_string(),
             enabled: true,
+            include_derived: false,
+            aggregate: None,
+            rate_limit: None,
+            circuit_breaker: None,
             properties: Webhook(properties),
         };
 
@@ -338,6 +588,284 @@ mod tests {
         let target = Target {
             name: "test".to_string(),
             enabled: true,
+            include_derived: false,
+            aggregate: None,
+            rate_limit: None,
+            circuit_breaker: None,
+            properties: Webhook(properties),
+        };
+
+        let handler = WebhookHandler::from(target);
+
+        let result = handler.handle_reading(&message, &context!()).await;
+
+        assert!(result.is_ok());
+        mock.assert_async().await;
+    }
+
+    fn oauth2_handler(server: &MockServer) -> WebhookHandler {
+        let mut properties = default_properties();
+
+        properties.url = server.url("/");
+        properties.auth = Some(WebhookAuth::OAuth2 {
+            oauth2: OAuth2Config {
+                token_url: server.url("/token"),
+                client_id: "pixy".to_string(),
+                client_secret: "secret".to_string(),
+                scopes: vec!["readings:write".to_string()],
+                audience: None,
+            },
+        });
+
+        WebhookHandler::from(Target {
+            name: "test".to_string(),
+            enabled: true,
+            include_derived: false,
+            aggregate: None,
+            rate_limit: None,
+            circuit_breaker: None,
+            properties: Webhook(properties),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_webhook_with_oauth2_caches_token() {
+        let server = MockServer::start_async().await;
+
+        let message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+
+        let token_mock = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/token")
+                    .x_www_form_urlencoded_tuple("grant_type", "client_credentials")
+                    .x_www_form_urlencoded_tuple("client_id", "pixy")
+                    .x_www_form_urlencoded_tuple("client_secret", "secret")
+                    .x_www_form_urlencoded_tuple("scope", "readings:write");
+                then.status(200).json_body(serde_json::json!({
+                    "access_token": "abc",
+                    "token_type": "Bearer",
+                    "expires_in": 3600,
+                }));
+            })
+            .await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/")
+                    .header("Authorization", "Bearer abc");
+                then.status(200);
+            })
+            .await;
+
+        let handler = oauth2_handler(&server);
+
+        assert!(handler.handle_reading(&message, &context!()).await.is_ok());
+        assert!(handler.handle_reading(&message, &context!()).await.is_ok());
+
+        token_mock.assert_hits_async(1).await;
+        mock.assert_hits_async(2).await;
+    }
+
+    #[tokio::test]
+    async fn test_webhook_with_oauth2_refreshes_rejected_token() {
+        let server = MockServer::start_async().await;
+
+        let message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+
+        let token_mock = server
+            .mock_async(|when, then| {
+                when.method(POST).path("/token");
+                then.status(200)
+                    .json_body(serde_json::json!({ "access_token": "abc" }));
+            })
+            .await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST).path("/");
+                then.status(401);
+            })
+            .await;
+
+        let handler = oauth2_handler(&server);
+
+        assert!(handler.handle_reading(&message, &context!()).await.is_err());
+
+        token_mock.assert_hits_async(2).await;
+        mock.assert_hits_async(2).await;
+    }
+
+    /// Builds a handler for the mock server, with the auth settings parsed from YAML.
+    fn handler_with_auth(server: &MockServer, auth: &str) -> WebhookHandler {
+        let mut properties = default_properties();
+
+        properties.url = server.url("/");
+        properties.auth = Some(serde_yaml::from_str(auth).unwrap());
+
+        WebhookHandler::from(Target {
+            name: "test".to_string(),
+            enabled: true,
+            include_derived: false,
+            aggregate: None,
+            rate_limit: None,
+            circuit_breaker: None,
+            properties: Webhook(properties),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_webhook_with_api_key() {
+        let server = MockServer::start_async().await;
+
+        let message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST).path("/").header("X-Token", "secret");
+                then.status(200);
+            })
+            .await;
+
+        let handler = handler_with_auth(&server, "apiKey: { header: X-Token, value: secret }");
+
+        assert!(handler.handle_reading(&message, &context!()).await.is_ok());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_webhook_with_api_key_in_query() {
+        let server = MockServer::start_async().await;
+
+        let message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST).path("/").query_param("code", "secret");
+                then.status(200);
+            })
+            .await;
+
+        let handler = handler_with_auth(&server, "apiKeyQuery: { param: code, value: secret }");
+
+        assert!(handler.handle_reading(&message, &context!()).await.is_ok());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_webhook_errors_hide_api_key_in_query() {
+        let server = MockServer::start_async().await;
+
+        let message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+
+        server
+            .mock_async(|when, then| {
+                when.method(POST).path("/");
+                then.status(403);
+            })
+            .await;
+
+        let handler = handler_with_auth(&server, "apiKeyQuery: { param: code, value: secret }");
+
+        let error = handler
+            .handle_reading(&message, &context!())
+            .await
+            .unwrap_err();
+
+        assert!(error.message.contains("403"));
+        assert!(!error.message.contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_with_aws_sig_v4() {
+        let server = MockServer::start_async().await;
+
+        let message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/")
+                    .header_exists("x-amz-date")
+                    .header("x-amz-security-token", "token")
+                    .matches(|request| {
+                        request.headers.iter().flatten().any(|(name, value)| {
+                            name.eq_ignore_ascii_case("authorization")
+                                && value.starts_with(
+                                    "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/",
+                                )
+                                && value.contains("/eu-west-1/lambda/aws4_request, SignedHeaders=content-type;host;x-amz-date;x-amz-security-token, Signature=")
+                        })
+                    });
+                then.status(200);
+            })
+            .await;
+
+        let handler = handler_with_auth(
+            &server,
+            r#"
+            awsSigV4:
+              region: eu-west-1
+              service: lambda
+              accessKeyId: AKIDEXAMPLE
+              secretAccessKey: secret
+              sessionToken: token
+            "#,
+        );
+
+        assert!(handler.handle_reading(&message, &context!()).await.is_ok());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_webhook_with_signing() {
+        let server = MockServer::start_async().await;
+
+        let message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+
+        let body_contents = serde_json::to_string(&message).unwrap();
+
+        let signature = sign(
+            Default::default(),
+            CanonicalFormat::Body,
+            b"secret",
+            0,
+            body_contents.as_bytes(),
+        );
+
+        let mock: httpmock::Mock<'_> = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/")
+                    .header("X-Signature", &signature)
+                    .header_exists("X-Pixy-Timestamp")
+                    .header("X-Signature-Key", "upstream")
+                    .body(&body_contents);
+                then.status(200);
+            })
+            .await;
+
+        let mut properties = default_properties();
+
+        properties.url = server.url("/");
+        properties.signing = Some(WebhookSigning {
+            secret: "secret".to_string(),
+            algorithm: Default::default(),
+            header: "X-Signature".to_string(),
+            timestamp_header: "X-Pixy-Timestamp".to_string(),
+            canonical: CanonicalFormat::Body,
+            key_id: Some("upstream".to_string()),
+            key_id_header: "X-Signature-Key".to_string(),
+        });
+
+        let target = Target {
+            name: "test".to_string(),
+            enabled: true,
+            include_derived: false,
+            aggregate: None,
+            rate_limit: None,
+            circuit_breaker: None,
             properties: Webhook(properties),
         };
 
@@ -376,6 +904,10 @@ mod tests {
         let target = Target {
             name: "test".to_string(),
             enabled: true,
+            include_derived: false,
+            aggregate: None,
+            rate_limit: None,
+            circuit_breaker: None,
             properties: Webhook(properties),
         };
 
@@ -386,4 +918,107 @@ mod tests {
         assert!(result.is_err());
         mock.assert_hits_async(2).await;
     }
+
+    fn handler_with_retry(server: &MockServer, retries: u8, retry: &str) -> WebhookHandler {
+        let mut properties = default_properties();
+
+        properties.url = server.url("/");
+        properties.retries = retries;
+        properties.retry = Some(serde_yaml::from_str(retry).unwrap());
+
+        WebhookHandler::from(Target {
+            name: "test".to_string(),
+            enabled: true,
+            include_derived: false,
+            aggregate: None,
+            rate_limit: None,
+            circuit_breaker: None,
+            properties: Webhook(properties),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_webhook_bad_requests_are_not_retried() {
+        let server = MockServer::start_async().await;
+
+        let message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST).path("/");
+                then.status(400);
+            })
+            .await;
+
+        let handler = handler_with_retry(&server, 3, "{ minBackoff: 0 }");
+
+        let result = handler.handle_reading(&message, &context!()).await;
+
+        assert!(result.is_err());
+        mock.assert_hits_async(1).await;
+    }
+
+    #[tokio::test]
+    async fn test_webhook_honours_retry_after() {
+        let server = MockServer::start_async().await;
+
+        let message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(POST).path("/");
+                then.status(429).header("Retry-After", "1");
+            })
+            .await;
+
+        let handler =
+            handler_with_retry(&server, 2, "{ minBackoff: 0, maxBackoff: 5, jitter: none }");
+
+        let started = Instant::now();
+        let result = handler.handle_reading(&message, &context!()).await;
+
+        assert!(result.is_err());
+        assert!(started.elapsed() >= Duration::from_secs(2));
+        mock.assert_hits_async(3).await;
+
+        // The target asks to wait longer than the deadline allows, so the request is
+        // not retried at all
+        let handler = handler_with_retry(&server, 2, "{ minBackoff: 0, deadline: 0 }");
+
+        let result = handler.handle_reading(&message, &context!()).await;
+
+        assert!(result.is_err());
+        mock.assert_hits_async(4).await;
+    }
+
+    #[test]
+    fn test_webhook_propagates_trace_context() {
+        use opentelemetry::trace::TracerProvider as _;
+        use opentelemetry_sdk::propagation::TraceContextPropagator;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        // A local propagator is used, so that the global one of other tests is not
+        // changed
+        let propagator = TraceContextPropagator::new();
+
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        // The trace started by the board, as received by the server
+        let carrier = std::collections::HashMap::from([(
+            String::from("traceparent"),
+            String::from("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        )]);
+        let span = info_span!("handle_reading");
+        span.set_parent(propagator.extract(&carrier));
+
+        let headers = inject_trace_context(&propagator, &span);
+
+        assert!(headers["traceparent"]
+            .to_str()
+            .unwrap()
+            .starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+    }
 }
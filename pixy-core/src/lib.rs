@@ -1,32 +1,88 @@
+pub mod aggregate;
+pub(crate) mod backlog;
+pub(crate) mod circuit_breaker;
 pub(crate) mod clients;
 pub mod config;
+pub mod dead_letter;
+pub(crate) mod dedup;
+pub mod derived;
 pub mod handlers;
+pub mod metrics;
+pub mod plausibility;
+pub(crate) mod rate_limit;
+pub mod reload;
+pub mod signing;
+pub mod status;
+pub mod timestamps;
 pub mod validation;
+pub(crate) mod verification;
 
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
 
-use crate::config::{ConfigFile, TargetProperties};
+use crate::aggregate::{AggregateSummary, Aggregator};
+use crate::circuit_breaker::CircuitBreaker;
+use crate::config::{ConfigFile, DeviceConfig, OnViolation, TargetProperties, TimestampConfig};
+use crate::dead_letter::{DeadLetter, DeadLetterFile, ReplaySummary};
+use crate::dedup::DedupCache;
+use crate::derived::DerivedReadings;
+use crate::metrics::{write_target_metric, GatewayMetrics};
+use crate::plausibility::{PlausibilityChecker, Suspect};
+use crate::rate_limit::{Decision, RateLimiter};
+use crate::status::{DeliveryStats, TargetStatus};
+use crate::verification::SignatureVerifier;
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
 use minijinja::{context, value::Value};
+use reqwest::header::HeaderMap;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, instrument};
+use tokio::sync::{broadcast, watch};
+use tracing::{debug, info, instrument, warn};
 
 /// A model describing the payload of the Enviro Pico board.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SensorMessage {
     /// The readings from the sensor.
     readings: Readings,
 
-    /// The timestamp of the reading.
+    /// The timestamp of the reading. Normalized to RFC 3339 by the gateway when it
+    /// can be parsed.
     timestamp: String,
 
+    /// The time the reading was received by the gateway.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    received_at: Option<DateTime<Utc>>,
+
     /// The metadata of the sensor.
     #[serde(flatten)]
     metadata: SensorMetadata,
+
+    /// The values derived from the readings. These are computed by the gateway
+    /// and only forwarded to targets that ask for them.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    derived: Option<DerivedReadings>,
+
+    /// The summary of an aggregation window, only set on the messages delivered
+    /// to targets that aggregate readings.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    aggregate: Option<AggregateSummary>,
+
+    /// The reasons the readings are not plausible, if they were accepted anyway.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    suspect: Option<Suspect>,
+
+    /// Why the reading could not be delivered, only set on the messages sent to
+    /// the dead letter target.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    dead_letter: Option<DeadLetter>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SensorMetadata {
     /// The nickname of the specific controller board.
     nickname: String,
@@ -38,7 +94,7 @@ pub struct SensorMetadata {
     uid: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Readings {
     // Sensors in every board
     /// The temperature in degrees Celsius.
@@ -67,71 +123,1061 @@ pub struct Readings {
     luminance: Option<u64>,
 }
 
+impl SensorMessage {
+    /// The unique identifier of the board that sent the reading.
+    pub fn uid(&self) -> &str {
+        &self.metadata.uid
+    }
+
+    /// The nickname of the board that sent the reading.
+    pub fn nickname(&self) -> &str {
+        &self.metadata.nickname
+    }
+
+    /// A reading from a fictional board, used to test the delivery to targets.
+    pub fn sample() -> Self {
+        let mut message: SensorMessage = serde_json::from_value(serde_json::json!({
+            "readings": {
+                "temperature": 21.5,
+                "pressure": 1013.25,
+                "humidity": 45.0,
+                "color_temperature": 5000,
+                "gas_resistance": 50000,
+                "aqi": 25.0,
+                "luminance": 300
+            },
+            "nickname": "pixy-test",
+            "model": "indoor",
+            "uid": "pixy-test",
+            "timestamp": Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        }))
+        .expect("sample reading is valid");
+
+        message.received_at = Some(Utc::now());
+
+        message
+    }
+}
+
+/// The error of a delivery to a target that failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeliveryError {
+    /// What went wrong.
+    pub message: String,
+
+    /// The number of requests that were made to the target.
+    pub attempts: u32,
+}
+
+impl From<String> for DeliveryError {
+    /// Creates the error of a delivery that failed before any request was made.
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            attempts: 0,
+        }
+    }
+}
+
+impl std::fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (after {} attempts)", self.message, self.attempts)
+    }
+}
+
 #[async_trait]
 pub trait SensorHandler: Send + Sync + std::fmt::Debug {
     /// Publishes the given reading to the target.
-    async fn handle_reading(&self, reading: &SensorMessage, context: &Value) -> Result<(), String>;
+    async fn handle_reading(
+        &self,
+        reading: &SensorMessage,
+        context: &Value,
+    ) -> Result<(), DeliveryError>;
 
     /// Returns the name of the handler.
     fn get_name(&self) -> &str;
 
     /// Returns whether or not the handler is enabled.
     fn is_enabled(&self) -> bool;
+
+    /// Describes the settings of the handler, without revealing its secrets.
+    fn describe(&self) -> String {
+        format!("{:?}", self)
+    }
 }
 
 #[async_trait]
 pub trait Gateway: Send + Sync + std::fmt::Debug {
+    /// Checks the raw payload of a reading before it is parsed, such as its
+    /// signature. Payloads that produce an error are rejected as unauthorized.
+    fn verify_payload(&self, _headers: &HeaderMap, _body: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Checks a reading before it is acknowledged, and prepares it to be handled.
+    /// Readings that produce an error are rejected, and should not be handled.
+    fn accept_reading(&self, _reading: &mut SensorMessage) -> Result<(), String> {
+        Ok(())
+    }
+
     async fn handle_reading(&self, reading: SensorMessage);
+
+    /// Returns the metrics of the gateway in the Prometheus text format.
+    fn metrics(&self) -> String {
+        String::new()
+    }
+
+    /// Returns the status of each target of the gateway.
+    fn targets(&self) -> Vec<TargetStatus> {
+        Vec::new()
+    }
+
+    /// Enables or disables the delivery of readings to a target.
+    fn set_target_enabled(&self, name: &str, _enabled: bool) -> Result<(), String> {
+        Err(format!("Target {} is not configured", name))
+    }
+
+    /// Delivers a sample reading to a target, to check that it can be reached.
+    async fn test_target(&self, name: &str) -> Result<(), String> {
+        Err(format!("Target {} is not configured", name))
+    }
+
+    /// Returns the latest reading of each board that sent one, along with its
+    /// derived values.
+    fn devices(&self) -> Vec<SensorMessage> {
+        Vec::new()
+    }
+
+    /// Subscribes to the readings accepted by the gateway, along with their derived
+    /// values. Returns `None` if the gateway does not publish its readings.
+    fn subscribe(&self) -> Option<broadcast::Receiver<SensorMessage>> {
+        None
+    }
+
+    /// When the configuration of the gateway was loaded, or `None` if it has not
+    /// been loaded.
+    fn loaded_at(&self) -> Option<DateTime<Utc>> {
+        None
+    }
+
+    /// Does the periodic work of the gateway, such as delivering the aggregation
+    /// windows that have elapsed. The server calls it every second.
+    async fn tick(&self) {}
+
+    /// Delivers the readings that the gateway is holding back, such as those of
+    /// open aggregation windows, and waits for the readings being handled. Called
+    /// before the gateway is replaced or stopped.
+    async fn flush(&self) {}
+
+    /// Loads the configuration of the gateway again.
+    async fn reload(&self) -> Result<(), String> {
+        Err(String::from("Reloading is not supported"))
+    }
+}
+
+/// A handler along with the gateway-level settings of its target.
+#[derive(Debug)]
+struct GatewayTarget {
+    handler: Box<dyn SensorHandler>,
+    include_derived: bool,
+    aggregator: Option<Aggregator>,
+    rate_limiter: Option<RateLimiter>,
+    circuit_breaker: Option<CircuitBreaker>,
+    /// Whether readings are delivered to the target, which can change at runtime.
+    enabled: AtomicBool,
+    deliveries: DeliveryStats,
+}
+
+impl GatewayTarget {
+    /// Delivers a message to the handler of the target, recording the result.
+    ///
+    /// Every delivery logs one event with the same fields, so that deliveries can
+    /// be followed in structured logs whatever the kind of target.
+    async fn deliver(&self, message: &SensorMessage, context: &Value) -> Result<(), DeliveryError> {
+        let started = Instant::now();
+        let result = self.handler.handle_reading(message, context).await;
+        let latency_ms = started.elapsed().as_millis() as u64;
+
+        match &result {
+            Ok(()) => info!(
+                handler = self.handler.get_name(),
+                uid = message.uid(),
+                status = "delivered",
+                latency_ms,
+                "Delivered reading"
+            ),
+            Err(e) => warn!(
+                handler = self.handler.get_name(),
+                uid = message.uid(),
+                status = "failed",
+                latency_ms,
+                attempts = e.attempts,
+                error = e.message,
+                "Failed to deliver reading"
+            ),
+        }
+
+        self.deliveries.record(&result, Utc::now());
+
+        result
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    fn status(&self) -> TargetStatus {
+        let mut status = TargetStatus {
+            name: self.handler.get_name().to_string(),
+            enabled: self.is_enabled(),
+            config: self.handler.describe(),
+            circuit: self
+                .circuit_breaker
+                .as_ref()
+                .map(|breaker| breaker.state().to_string()),
+            backlog: self
+                .circuit_breaker
+                .as_ref()
+                .and_then(|breaker| Some(breaker.backlog.as_ref()?.len())),
+            deliveries: 0,
+            failures: 0,
+            last_success: None,
+            last_failure: None,
+            last_error: None,
+        };
+
+        self.deliveries.fill(&mut status);
+
+        status
+    }
 }
 
 #[derive(Debug)]
 pub struct SensorGateway {
-    handlers: Vec<Box<dyn SensorHandler>>,
+    targets: Vec<GatewayTarget>,
+    devices: HashMap<String, DeviceConfig>,
     env_vars: HashMap<String, String>,
+    dedup: Option<DedupCache>,
+    plausibility: Option<PlausibilityChecker>,
+    timestamps: Option<TimestampConfig>,
+    verifier: Option<SignatureVerifier>,
+    dead_letter_file: Option<DeadLetterFile>,
+    dead_letter_target: Option<String>,
+    metrics: GatewayMetrics,
+    /// Publishes the readings that are relayed to the targets.
+    readings: broadcast::Sender<SensorMessage>,
+    /// The latest reading of each board, keyed by its `uid`.
+    latest: Mutex<HashMap<String, SensorMessage>>,
+    /// When the configuration of the gateway was loaded.
+    loaded_at: DateTime<Utc>,
+    /// The number of readings being handled.
+    in_flight: watch::Sender<usize>,
+}
+
+/// Counts a reading as being handled until it is dropped.
+pub(crate) struct InFlight(watch::Sender<usize>);
+
+impl InFlight {
+    fn new(in_flight: &watch::Sender<usize>) -> Self {
+        in_flight.send_modify(|in_flight| *in_flight += 1);
+        Self(in_flight.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.send_modify(|in_flight| *in_flight -= 1);
+    }
 }
 
-impl From<ConfigFile> for SensorGateway {
-    fn from(config: ConfigFile) -> Self {
-        let mut handlers: Vec<Box<dyn SensorHandler>> = Vec::new();
+/// The number of readings kept for subscribers that are slow to receive them.
+/// Subscribers that fall further behind miss the oldest readings.
+pub(crate) const READINGS_CAPACITY: usize = 64;
+
+/// The number of boards whose latest reading is kept when no `devices` are
+/// configured. The board that was seen the longest ago is forgotten to make room
+/// for a new one.
+pub(crate) const MAX_LATEST_DEVICES: usize = 256;
+
+impl TryFrom<ConfigFile> for SensorGateway {
+    type Error = String;
 
-        let client = clients::get_default_webhook_client();
+    /// Builds the gateway and the clients of its targets. Fails if the settings of
+    /// a client, such as its TLS or proxy settings, are invalid.
+    fn try_from(config: ConfigFile) -> Result<Self, Self::Error> {
+        let mut targets: Vec<GatewayTarget> = Vec::new();
 
-        let env_vars = std::env::vars()
+        let env_vars: HashMap<String, String> = std::env::vars()
             .filter(|(key, _)| key.starts_with("PIXY_"))
             .map(|(key, value)| (key.replace("PIXY_", ""), value))
             .collect();
 
+        let env_context = context!(env => env_vars);
+
+        let client = match &config.proxy {
+            Some(proxy) => clients::get_webhook_client(None, Some(proxy), &env_context)
+                .map_err(|e| format!("Invalid proxy settings: {}", e))?,
+            None => clients::get_default_webhook_client(),
+        };
+
         for target in config.targets {
-            match target.properties {
-                TargetProperties::Webhook(_) => {
-                    handlers.push(Box::new(handlers::WebhookHandler::new(
-                        target,
-                        client.clone(),
-                    )));
+            let include_derived = target.include_derived;
+            let aggregator = target.aggregate.as_ref().map(Aggregator::new);
+            let rate_limiter = target.rate_limit.as_ref().map(RateLimiter::new);
+            let circuit_breaker = target.circuit_breaker.as_ref().map(CircuitBreaker::new);
+
+            let handler: Box<dyn SensorHandler> = match target.properties {
+                TargetProperties::Webhook(ref properties) => {
+                    let client = if properties.tls.is_some() || properties.proxy.is_some() {
+                        clients::get_webhook_client(
+                            properties.tls.as_ref(),
+                            properties.proxy.as_ref().or(config.proxy.as_ref()),
+                            &env_context,
+                        )
+                        .map_err(|e| {
+                            format!("Invalid client settings for target {}: {}", target.name, e)
+                        })?
+                    } else {
+                        client.clone()
+                    };
+
+                    Box::new(handlers::WebhookHandler::new(target, client))
                 }
                 TargetProperties::Unknown => {
                     tracing::warn!("Unknown target properties for target {}", target.name);
+                    continue;
+                }
+            };
+
+            targets.push(GatewayTarget {
+                enabled: AtomicBool::new(handler.is_enabled()),
+                handler,
+                include_derived,
+                aggregator,
+                rate_limiter,
+                circuit_breaker,
+                deliveries: DeliveryStats::default(),
+            });
+        }
+
+        let dead_letter = config.dead_letter.unwrap_or_default();
+
+        if let Some(name) = &dead_letter.target {
+            if !targets
+                .iter()
+                .any(|target| target.handler.get_name() == name)
+            {
+                warn!("Dead letter target {} is not configured", name);
+            }
+        }
+
+        Ok(Self {
+            targets,
+            devices: config.devices,
+            env_vars,
+            dedup: config.deduplication.as_ref().map(DedupCache::new),
+            plausibility: config.plausibility.as_ref().map(PlausibilityChecker::new),
+            timestamps: config.timestamps,
+            verifier: config
+                .verification
+                .as_ref()
+                .map(SignatureVerifier::new)
+                .transpose()?,
+            dead_letter_file: dead_letter.path.as_deref().map(DeadLetterFile::new),
+            dead_letter_target: dead_letter.target,
+            metrics: GatewayMetrics::default(),
+            readings: broadcast::channel(READINGS_CAPACITY).0,
+            latest: Mutex::new(HashMap::new()),
+            loaded_at: Utc::now(),
+            in_flight: watch::Sender::new(0),
+        })
+    }
+}
+
+impl SensorGateway {
+    /// Publishes the readings of the gateway to an existing channel, so that its
+    /// subscribers keep receiving readings when the gateway is replaced.
+    pub fn with_readings(self, readings: broadcast::Sender<SensorMessage>) -> Self {
+        Self { readings, ..self }
+    }
+
+    /// Counts a reading as being handled by the gateway until the guard is
+    /// dropped, before the reading is passed to `handle_reading`.
+    pub(crate) fn start_reading(&self) -> InFlight {
+        InFlight::new(&self.in_flight)
+    }
+
+    /// Takes over the runtime state of the targets of a previous gateway that are
+    /// still configured, matched by name: whether an admin enabled or disabled
+    /// them, and the results of their deliveries. The latest reading of each
+    /// board is kept too.
+    pub fn carry_over(&self, previous: &SensorGateway) {
+        for target in &self.targets {
+            let Some(old) = previous.target(target.handler.get_name()) else {
+                continue;
+            };
+
+            // Only changes made at runtime are kept, so that changes to `enabled`
+            // in the file still apply
+            if old.is_enabled() != old.handler.is_enabled() {
+                target.enabled.store(old.is_enabled(), Ordering::Relaxed);
+            }
+
+            target.deliveries.carry_over(&old.deliveries);
+        }
+
+        let previous = previous
+            .latest
+            .lock()
+            .expect("latest readings lock poisoned");
+        for reading in previous.values() {
+            self.remember_latest(reading);
+        }
+    }
+
+    /// Keeps the reading as the latest one of its board. Only the boards in
+    /// `devices` are kept when some are configured, otherwise at most
+    /// `MAX_LATEST_DEVICES` boards are kept, so that readings from unknown boards
+    /// cannot grow the map without bound.
+    fn remember_latest(&self, reading: &SensorMessage) {
+        let uid = &reading.metadata.uid;
+        if !self.devices.is_empty() && !self.devices.contains_key(uid) {
+            return;
+        }
+
+        let mut latest = self.latest.lock().expect("latest readings lock poisoned");
+        if !latest.contains_key(uid) && latest.len() >= MAX_LATEST_DEVICES {
+            let oldest = latest
+                .iter()
+                .min_by_key(|(_, reading)| reading.received_at)
+                .map(|(uid, _)| uid.clone());
+            if let Some(oldest) = oldest {
+                latest.remove(&oldest);
+            }
+        }
+        latest.insert(uid.clone(), reading.clone());
+    }
+
+    /// Whether a reading should be relayed to a target. When implausible readings
+    /// are routed, the targets they are routed to only receive implausible readings.
+    fn should_relay(&self, target: &GatewayTarget, reading: &SensorMessage) -> bool {
+        if !target.is_enabled() || self.is_dead_letter_target(target) {
+            return false;
+        }
+
+        match &self.plausibility {
+            Some(checker) if checker.config.on_violation == OnViolation::Route => {
+                let routed = checker
+                    .config
+                    .route_to
+                    .iter()
+                    .any(|name| name == target.handler.get_name());
+
+                routed == reading.suspect.is_some()
+            }
+            _ => true,
+        }
+    }
+
+    /// Finds a target by its name.
+    fn target(&self, name: &str) -> Option<&GatewayTarget> {
+        self.targets
+            .iter()
+            .find(|target| target.handler.get_name() == name)
+    }
+
+    /// Whether a target only receives dead letters.
+    fn is_dead_letter_target(&self, target: &GatewayTarget) -> bool {
+        self.dead_letter_target.as_deref() == Some(target.handler.get_name())
+    }
+
+    /// Builds the template context for a message delivered to a target.
+    fn context(&self, message: &SensorMessage, derived: &DerivedReadings) -> Value {
+        context!(env => self.env_vars, reading => message, derived => derived)
+    }
+
+    /// Builds the template context for a message that is delivered again, such as
+    /// from a backlog, computing its derived values again.
+    fn redelivery_context(&self, message: &SensorMessage) -> Value {
+        let altitude = self
+            .devices
+            .get(&message.metadata.uid)
+            .and_then(|device| device.altitude);
+
+        self.context(
+            message,
+            &DerivedReadings::compute(&message.readings, altitude),
+        )
+    }
+
+    /// Sends a reading that could not be delivered to a target to the dead letter
+    /// destinations.
+    async fn dead_letter(
+        &self,
+        target: &GatewayTarget,
+        message: &SensorMessage,
+        error: DeliveryError,
+    ) {
+        if self.dead_letter_file.is_none() && self.dead_letter_target.is_none() {
+            return;
+        }
+
+        let dead_letter = DeadLetter {
+            target: target.handler.get_name().to_string(),
+            error: error.message,
+            attempts: error.attempts,
+            failed_at: Utc::now(),
+        };
+
+        self.metrics.dead_letter();
+
+        if let Some(file) = &self.dead_letter_file {
+            file.push(&dead_letter, message);
+        }
+
+        // Readings that cannot be delivered to the dead letter target are not sent
+        // back to it
+        if self.is_dead_letter_target(target) {
+            return;
+        }
+
+        let Some(dead_letter_target) = self
+            .targets
+            .iter()
+            .find(|target| self.is_dead_letter_target(target) && target.is_enabled())
+        else {
+            return;
+        };
+
+        let message = SensorMessage {
+            dead_letter: Some(dead_letter),
+            ..message.clone()
+        };
+        let ctx = self.redelivery_context(&message);
+
+        if let Err(e) = dead_letter_target.deliver(&message, &ctx).await {
+            tracing::error!(error = %e, "Failed to send dead letter");
+        }
+    }
+
+    /// Delivers the dead letters stored in the dead letter file to the targets they
+    /// were meant for. Dead letters that fail again are kept in the file.
+    ///
+    /// ## Arguments
+    ///
+    /// * `target` - Only replays the dead letters of this target, if set.
+    pub async fn replay_dead_letters(&self, target: Option<&str>) -> Result<ReplaySummary, String> {
+        let file = self
+            .dead_letter_file
+            .as_ref()
+            .ok_or_else(|| String::from("No dead letter file is configured"))?;
+
+        let stored = file.take()?;
+
+        let mut summary = ReplaySummary::default();
+        let mut remaining = Vec::new();
+
+        for (dead_letter, message) in stored.dead_letters {
+            if target.is_some_and(|name| name != dead_letter.target) {
+                remaining.push((dead_letter, message));
+                continue;
+            }
+
+            let Some(destination) = self.target(&dead_letter.target) else {
+                warn!(
+                    target = dead_letter.target,
+                    "Target of dead letter is not configured"
+                );
+                summary.failed += 1;
+                remaining.push((dead_letter, message));
+                continue;
+            };
+
+            let ctx = self.redelivery_context(&message);
+
+            match destination.deliver(&message, &ctx).await {
+                Ok(()) => summary.replayed += 1,
+                Err(e) => {
+                    summary.failed += 1;
+                    remaining.push((
+                        DeadLetter {
+                            error: e.message,
+                            attempts: dead_letter.attempts + e.attempts,
+                            failed_at: Utc::now(),
+                            ..dead_letter
+                        },
+                        message,
+                    ));
+                }
+            }
+        }
+
+        file.restore(&remaining, &stored.unreadable)?;
+
+        Ok(summary)
+    }
+
+    /// Relays a message to a single target, applying the settings of the target.
+    async fn relay(
+        &self,
+        target: &GatewayTarget,
+        message: &SensorMessage,
+        derived: &DerivedReadings,
+    ) {
+        let handler = &target.handler;
+
+        let aggregated;
+        let message = match &target.aggregator {
+            Some(aggregator) => match aggregator.push(message, Instant::now()) {
+                Some(message) => {
+                    aggregated = message;
+                    &aggregated
+                }
+                None => {
+                    debug!(
+                        handler = handler.get_name(),
+                        "Buffered reading for aggregation"
+                    );
+                    return;
+                }
+            },
+            None => message,
+        };
+
+        self.send(target, message, self.context(message, derived))
+            .await;
+    }
+
+    /// Delivers the aggregation windows of the targets, either those that have
+    /// elapsed or every one of them.
+    async fn flush_windows(&self, all: bool) {
+        let now = Instant::now();
+
+        let windows = self.targets.iter().flat_map(|target| {
+            let messages = match &target.aggregator {
+                Some(aggregator) if all => aggregator.drain(),
+                Some(aggregator) => aggregator.expired(now),
+                None => Vec::new(),
+            };
+
+            messages.into_iter().map(move |message| (target, message))
+        });
+
+        join_all(windows.map(|(target, message)| async move {
+            let ctx = self.redelivery_context(&message);
+            self.send(target, &message, ctx).await
+        }))
+        .await;
+    }
+
+    /// Sends a message to a single target, applying its rate limit and circuit
+    /// breaker.
+    async fn send(&self, target: &GatewayTarget, message: &SensorMessage, ctx: Value) {
+        let handler = &target.handler;
+
+        let mut ctx = ctx;
+        let mut message = Cow::Borrowed(message);
+
+        if let Some(limiter) = &target.rate_limiter {
+            match limiter.check(&message, &ctx, Instant::now()) {
+                Decision::Send => {}
+                Decision::Wait(delay) => {
+                    debug!(
+                        handler = handler.get_name(),
+                        ?delay,
+                        "Waiting for rate limit"
+                    );
+
+                    limiter.wait(delay).await;
+
+                    if let Some((latest, latest_ctx)) = limiter.release(&message.metadata.uid) {
+                        message = Cow::Owned(latest);
+                        ctx = latest_ctx;
+                    }
+                }
+                Decision::Coalesced => {
+                    debug!(
+                        handler = handler.get_name(),
+                        "Coalesced reading with pending delivery"
+                    );
+                    return;
+                }
+                Decision::Drop => {
+                    warn!(
+                        handler = handler.get_name(),
+                        "Dropped reading over the rate limit"
+                    );
+                    return;
                 }
             }
         }
 
-        Self { handlers, env_vars }
+        let Some(breaker) = &target.circuit_breaker else {
+            if let Err(e) = target.deliver(&message, &ctx).await {
+                tracing::error!(?handler, "Handler produced error");
+                self.dead_letter(target, &message, e).await;
+            }
+            return;
+        };
+
+        if !breaker.allow(Instant::now()) {
+            self.metrics.circuit_rejection();
+            self.hold(target, breaker, &message).await;
+            return;
+        }
+
+        // The readings stored while the circuit was open are delivered first, so
+        // that the target receives the readings in order. The first of them probes
+        // the target when the circuit is half-open.
+        if let Some(backlog) = breaker.backlog.as_ref().filter(|b| b.len() > 0) {
+            if !self.replay(target, breaker, backlog.drain()).await {
+                self.hold(target, breaker, &message).await;
+                return;
+            }
+        }
+
+        if let Err(e) = target.deliver(&message, &ctx).await {
+            tracing::error!(?handler, "Handler produced error");
+            breaker.record_failure(Instant::now());
+            self.dead_letter(target, &message, e).await;
+            return;
+        }
+
+        breaker.record_success();
+    }
+
+    /// Stores a reading that cannot be delivered while the circuit of a target is
+    /// open in its backlog, or sends it to the dead letter destinations if the
+    /// backlog cannot take it.
+    async fn hold(
+        &self,
+        target: &GatewayTarget,
+        breaker: &CircuitBreaker,
+        message: &SensorMessage,
+    ) {
+        let handler = target.handler.get_name();
+
+        if breaker
+            .backlog
+            .as_ref()
+            .is_some_and(|backlog| backlog.push(message))
+        {
+            debug!(handler, "Stored reading in backlog while circuit is open");
+            return;
+        }
+
+        warn!(handler, "Dropped reading while circuit is open");
+
+        self.dead_letter(
+            target,
+            message,
+            DeliveryError::from(String::from("Circuit is open")),
+        )
+        .await;
+    }
+
+    /// Delivers the backlogs of the enabled targets whose circuit lets a delivery
+    /// through, so that they do not wait for the next reading to be delivered.
+    async fn flush_backlogs(&self) {
+        let backlogs = self.targets.iter().filter_map(|target| {
+            let breaker = target.circuit_breaker.as_ref()?;
+            let backlog = breaker.backlog.as_ref().filter(|b| b.len() > 0)?;
+
+            (target.is_enabled() && breaker.allow(Instant::now()))
+                .then_some((target, breaker, backlog))
+        });
+
+        join_all(
+            backlogs
+                .map(|(target, breaker, backlog)| self.replay(target, breaker, backlog.drain())),
+        )
+        .await;
+    }
+
+    /// Delivers the readings stored while the circuit of a target was open, oldest
+    /// first. Stops at the first failure, storing the readings that were not
+    /// delivered again, and returns whether every reading was delivered.
+    async fn replay(
+        &self,
+        target: &GatewayTarget,
+        breaker: &CircuitBreaker,
+        messages: Vec<SensorMessage>,
+    ) -> bool {
+        let handler = &target.handler;
+
+        info!(
+            handler = handler.get_name(),
+            count = messages.len(),
+            "Delivering readings from backlog"
+        );
+
+        let mut messages = messages.into_iter();
+
+        for message in messages.by_ref() {
+            let ctx = self.redelivery_context(&message);
+
+            if let Err(e) = target.deliver(&message, &ctx).await {
+                tracing::error!(?handler, "Handler produced error");
+                breaker.record_failure(Instant::now());
+
+                // Readings that cannot be stored again are not lost, but sent to
+                // the dead letter destinations
+                if !breaker
+                    .backlog
+                    .as_ref()
+                    .is_some_and(|backlog| backlog.push(&message))
+                {
+                    self.dead_letter(target, &message, e).await;
+                }
+
+                for message in messages {
+                    self.hold(target, breaker, &message).await;
+                }
+                return false;
+            }
+
+            breaker.record_success();
+        }
+
+        true
     }
 }
 
 #[async_trait]
 impl Gateway for SensorGateway {
-    #[instrument]
+    fn verify_payload(&self, headers: &HeaderMap, body: &[u8]) -> Result<(), String> {
+        let Some(verifier) = &self.verifier else {
+            return Ok(());
+        };
+
+        let ctx = context!(env => self.env_vars);
+
+        verifier
+            .verify(headers, body, &self.devices, &ctx, Utc::now().timestamp())
+            .inspect_err(|e| {
+                warn!(error = e, "Rejected payload with an invalid signature");
+                self.metrics.invalid_signature();
+            })
+    }
+
+    fn accept_reading(&self, reading: &mut SensorMessage) -> Result<(), String> {
+        let clock_offset = self
+            .devices
+            .get(&reading.metadata.uid)
+            .map(|device| device.clock_offset)
+            .unwrap_or_default();
+
+        timestamps::normalize(reading, self.timestamps.as_ref(), clock_offset, Utc::now())?;
+
+        let Some(checker) = &self.plausibility else {
+            return Ok(());
+        };
+
+        let Some(suspect) = checker.check(reading, Instant::now()) else {
+            return Ok(());
+        };
+
+        warn!(
+            uid = reading.metadata.uid,
+            violations = ?suspect.violations,
+            "Received implausible reading"
+        );
+        self.metrics.implausible_reading();
+
+        if checker.config.on_violation == OnViolation::Reject {
+            return Err(format!(
+                "Implausible reading: {}",
+                suspect.violations.join(", ")
+            ));
+        }
+
+        reading.suspect = Some(suspect);
+
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(uid = reading.uid()))]
     async fn handle_reading(&self, reading: SensorMessage) {
         debug!("Handling reading: {:?}", &reading);
 
-        let ctx = context!(env => self.env_vars, reading => reading);
+        let _in_flight = InFlight::new(&self.in_flight);
 
-        for handler in &self.handlers {
-            let _ = handler.handle_reading(&reading, &ctx).await.map_err(|_| {
-                tracing::error!(?handler, "Handler produced error");
-            });
+        self.metrics.reading_received();
+
+        if let Some(dedup) = &self.dedup {
+            if !dedup.insert(&reading.metadata.uid, &reading.timestamp) {
+                info!(
+                    uid = reading.metadata.uid,
+                    timestamp = reading.timestamp,
+                    "Suppressed duplicate reading"
+                );
+                self.metrics.duplicate_suppressed();
+                return;
+            }
+        }
+
+        if let (Some(checker), Some(_)) = (&self.plausibility, &reading.suspect) {
+            if checker.config.on_violation == OnViolation::Drop {
+                info!(uid = reading.metadata.uid, "Dropped implausible reading");
+                return;
+            }
+        }
+
+        let altitude = self
+            .devices
+            .get(&reading.metadata.uid)
+            .and_then(|device| device.altitude);
+
+        let derived = DerivedReadings::compute(&reading.readings, altitude);
+
+        let with_derived = SensorMessage {
+            derived: Some(derived.clone()),
+            ..reading.clone()
+        };
+
+        self.remember_latest(&with_derived);
+
+        // Sending only fails when there are no subscribers
+        let _ = self.readings.send(with_derived.clone());
+
+        // Targets are relayed to concurrently, so that a target waiting on its
+        // rate limit does not hold up the others
+        let targets = self
+            .targets
+            .iter()
+            .filter(|target| self.should_relay(target, &reading));
+
+        join_all(targets.map(|target| {
+            let message = if target.include_derived {
+                &with_derived
+            } else {
+                &reading
+            };
+
+            self.relay(target, message, &derived)
+        }))
+        .await;
+    }
+
+    fn metrics(&self) -> String {
+        let mut out = self.metrics.render();
+
+        let breakers: Vec<_> = self
+            .targets
+            .iter()
+            .filter_map(|target| {
+                let breaker = target.circuit_breaker.as_ref()?;
+                Some((target.handler.get_name(), breaker))
+            })
+            .collect();
+
+        let states: Vec<_> = breakers
+            .iter()
+            .map(|(name, breaker)| (*name, breaker.state().gauge()))
+            .collect();
+
+        let backlogs: Vec<_> = breakers
+            .iter()
+            .filter_map(|(name, breaker)| Some((*name, breaker.backlog.as_ref()?.len() as u64)))
+            .collect();
+
+        write_target_metric(
+            &mut out,
+            "pixy_circuit_state",
+            "gauge",
+            "The state of the circuit of the target: 0 closed, 1 open, 2 half-open",
+            &states,
+        );
+
+        write_target_metric(
+            &mut out,
+            "pixy_backlog_readings",
+            "gauge",
+            "The number of readings waiting in the backlog of the target",
+            &backlogs,
+        );
+
+        out
+    }
+
+    fn targets(&self) -> Vec<TargetStatus> {
+        self.targets.iter().map(GatewayTarget::status).collect()
+    }
+
+    fn devices(&self) -> Vec<SensorMessage> {
+        let mut devices: Vec<_> = self
+            .latest
+            .lock()
+            .expect("latest readings lock poisoned")
+            .values()
+            .cloned()
+            .collect();
+
+        devices.sort_by(|a, b| a.metadata.nickname.cmp(&b.metadata.nickname));
+
+        devices
+    }
+
+    fn subscribe(&self) -> Option<broadcast::Receiver<SensorMessage>> {
+        Some(self.readings.subscribe())
+    }
+
+    fn loaded_at(&self) -> Option<DateTime<Utc>> {
+        Some(self.loaded_at)
+    }
+
+    async fn tick(&self) {
+        futures::join!(self.flush_windows(false), self.flush_backlogs());
+    }
+
+    async fn flush(&self) {
+        // The limiters are flushed first, so that the windows are not held up by
+        // the rate limits either
+        for limiter in self.targets.iter().filter_map(|t| t.rate_limiter.as_ref()) {
+            limiter.flush();
+        }
+
+        // The readings being handled can still be added to the windows, so they
+        // are waited for before the windows are drained
+        let _ = self
+            .in_flight
+            .subscribe()
+            .wait_for(|in_flight| *in_flight == 0)
+            .await;
+
+        self.flush_windows(true).await;
+    }
+
+    fn set_target_enabled(&self, name: &str, enabled: bool) -> Result<(), String> {
+        let target = self
+            .target(name)
+            .ok_or_else(|| format!("Target {} is not configured", name))?;
+
+        info!(handler = name, enabled, "Changed whether target is enabled");
+        target.enabled.store(enabled, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    async fn test_target(&self, name: &str) -> Result<(), String> {
+        let target = self
+            .target(name)
+            .ok_or_else(|| format!("Target {} is not configured", name))?;
+
+        let mut message = SensorMessage::sample();
+        let derived = DerivedReadings::compute(&message.readings, None);
+
+        if target.include_derived {
+            message.derived = Some(derived.clone());
         }
+
+        // Test deliveries skip the settings of the target, and are not recorded in
+        // its status
+        target
+            .handler
+            .handle_reading(&message, &self.context(&message, &derived))
+            .await
+            .map_err(|e| e.to_string())
     }
 }
 
@@ -159,4 +1205,850 @@ mod tests {
     test_deser!(
         sensor_deserialize_works: "../example-configs/test-sensor.json",
     );
+
+    /// Builds a gateway with a single webhook target pointing at the mock server.
+    /// The `settings` are added to the target, and must be a single line of YAML.
+    fn gateway_for(server: &httpmock::MockServer, settings: &str) -> SensorGateway {
+        let config = format!(
+            r#"
+            targets:
+              - name: test
+                {}
+                webhook:
+                  url: "{}"
+                  retries: 0
+            devices:
+              e6614864d3898034:
+                altitude: 120
+            "#,
+            settings,
+            server.url("/")
+        );
+
+        SensorGateway::try_from(serde_yaml::from_str::<ConfigFile>(&config).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_derived_values_are_forwarded_when_enabled() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .body_contains("\"dew_point\"")
+                    .body_contains("\"sea_level_pressure\"");
+                then.status(200);
+            })
+            .await;
+
+        let gateway = gateway_for(&server, "includeDerived: true");
+
+        gateway
+            .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+            .await;
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_derived_values_are_not_forwarded_by_default() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST).matches(|req| {
+                    !String::from_utf8_lossy(req.body.as_deref().unwrap_or_default())
+                        .contains("derived")
+                });
+                then.status(200);
+            })
+            .await;
+
+        let gateway = gateway_for(&server, "enabled: true");
+
+        gateway
+            .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+            .await;
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_aggregated_message_is_forwarded() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .body_contains("\"aggregate\"")
+                    .body_contains("\"count\":1");
+                then.status(200);
+            })
+            .await;
+
+        // An empty window elapses as soon as the first reading is buffered
+        let gateway = gateway_for(&server, "aggregate: { window: 0 }");
+
+        gateway
+            .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+            .await;
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_aggregated_readings_are_buffered() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.any_request();
+                then.status(200);
+            })
+            .await;
+
+        let gateway = gateway_for(&server, "aggregate: { window: 3600 }");
+
+        gateway
+            .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+            .await;
+
+        mock.assert_hits_async(0).await;
+    }
+
+    #[tokio::test]
+    async fn test_open_windows_are_flushed() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .body_contains("\"count\":2");
+                then.status(200);
+            })
+            .await;
+
+        let gateway = gateway_for(&server, "aggregate: { window: 3600 }");
+
+        for _ in 0..2 {
+            gateway
+                .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+                .await;
+        }
+
+        gateway.tick().await;
+        mock.assert_hits_async(0).await;
+
+        gateway.flush().await;
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_flush_waits_for_readings_being_handled() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .body_contains("\"count\":1");
+                then.status(200);
+            })
+            .await;
+
+        let gateway = gateway_for(&server, "aggregate: { window: 3600 }");
+
+        // A reading that was handed to the gateway before it is flushed
+        let in_flight = gateway.start_reading();
+
+        let flush = gateway.flush();
+        tokio::pin!(flush);
+
+        let waited = tokio::time::timeout(std::time::Duration::from_millis(50), &mut flush).await;
+        assert!(waited.is_err());
+
+        gateway
+            .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+            .await;
+        drop(in_flight);
+
+        flush.await;
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_readings_are_dropped() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.any_request();
+                then.status(200);
+            })
+            .await;
+
+        let gateway = gateway_for(&server, "rateLimit: { maxRequests: 1, period: 3600 }");
+
+        for _ in 0..3 {
+            gateway
+                .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+                .await;
+        }
+
+        mock.assert_hits_async(1).await;
+    }
+
+    /// A handler that counts the readings it receives, so that tests with a
+    /// paused clock do not wait on the network.
+    #[derive(Debug, Default)]
+    struct CountingHandler {
+        count: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl SensorHandler for CountingHandler {
+        async fn handle_reading(
+            &self,
+            _message: &SensorMessage,
+            _context: &Value,
+        ) -> Result<(), DeliveryError> {
+            self.count.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn get_name(&self) -> &str {
+            "counting"
+        }
+
+        fn is_enabled(&self) -> bool {
+            true
+        }
+    }
+
+    fn counting_gateway(
+        settings: &str,
+    ) -> (
+        SensorGateway,
+        std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    ) {
+        let server = httpmock::MockServer::start();
+        let mut gateway = gateway_for(&server, settings);
+
+        let handler = CountingHandler::default();
+        let count = handler.count.clone();
+        gateway.targets[0].handler = Box::new(handler);
+
+        (gateway, count)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limited_readings_are_queued() {
+        let (gateway, count) =
+            counting_gateway("rateLimit: { maxRequests: 1, period: 60, onLimit: queue }");
+        let started = tokio::time::Instant::now();
+
+        for _ in 0..2 {
+            gateway
+                .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+                .await;
+        }
+
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+        assert!(started.elapsed() >= std::time::Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queued_readings_are_flushed() {
+        let (gateway, count) =
+            counting_gateway("rateLimit: { maxRequests: 1, period: 3600, onLimit: queue }");
+        let started = tokio::time::Instant::now();
+
+        let readings = async {
+            for _ in 0..2 {
+                gateway
+                    .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+                    .await;
+            }
+        };
+
+        tokio::join!(readings, async {
+            // Lets the second reading start waiting for its slot
+            tokio::task::yield_now().await;
+            gateway.flush().await
+        });
+
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+        assert!(started.elapsed() < std::time::Duration::from_secs(3600));
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_readings_are_suppressed() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.any_request();
+                then.status(200);
+            })
+            .await;
+
+        let config = format!(
+            r#"
+            targets:
+              - name: test
+                webhook:
+                  url: "{}"
+            deduplication:
+              ttl: 600
+            "#,
+            server.url("/")
+        );
+
+        let gateway =
+            SensorGateway::try_from(serde_yaml::from_str::<ConfigFile>(&config).unwrap()).unwrap();
+
+        for _ in 0..2 {
+            gateway
+                .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+                .await;
+        }
+
+        mock.assert_hits_async(1).await;
+        assert_eq!(gateway.metrics.readings_received(), 2);
+        assert_eq!(gateway.metrics.duplicates_suppressed(), 1);
+    }
+
+    fn implausible_reading() -> SensorMessage {
+        let mut reading = deserialize_file("../example-configs/test-sensor.json");
+
+        reading.readings.temperature = 850.0;
+
+        reading
+    }
+
+    fn plausibility_gateway(server: &httpmock::MockServer, on_violation: &str) -> SensorGateway {
+        let config = format!(
+            r#"
+            targets:
+              - name: main
+                webhook:
+                  url: "{}"
+              - name: quarantine
+                webhook:
+                  url: "{}"
+            plausibility:
+              fields:
+                temperature: {{ min: -40, max: 85 }}
+              onViolation: {}
+              routeTo: [quarantine]
+            "#,
+            server.url("/main"),
+            server.url("/quarantine"),
+            on_violation
+        );
+
+        SensorGateway::try_from(serde_yaml::from_str::<ConfigFile>(&config).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_implausible_readings_are_rejected() {
+        let server = httpmock::MockServer::start();
+        let gateway = plausibility_gateway(&server, "reject");
+
+        let mut plausible = deserialize_file("../example-configs/test-sensor.json");
+        assert!(gateway.accept_reading(&mut plausible).is_ok());
+
+        let result = gateway.accept_reading(&mut implausible_reading());
+
+        assert_eq!(
+            result,
+            Err(String::from(
+                "Implausible reading: temperature 850 is above the maximum of 85"
+            ))
+        );
+        assert_eq!(gateway.metrics.implausible_readings(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_implausible_readings_are_dropped() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.any_request();
+                then.status(200);
+            })
+            .await;
+
+        let gateway = plausibility_gateway(&server, "drop");
+
+        let mut reading = implausible_reading();
+        assert!(gateway.accept_reading(&mut reading).is_ok());
+
+        gateway.handle_reading(reading).await;
+
+        mock.assert_hits_async(0).await;
+    }
+
+    #[tokio::test]
+    async fn test_implausible_readings_are_tagged() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .body_contains("\"suspect\":{\"violations\":[");
+                then.status(200);
+            })
+            .await;
+
+        let gateway = plausibility_gateway(&server, "tag");
+
+        let mut reading = implausible_reading();
+        assert!(gateway.accept_reading(&mut reading).is_ok());
+
+        gateway.handle_reading(reading).await;
+
+        mock.assert_hits_async(2).await;
+    }
+
+    #[tokio::test]
+    async fn test_implausible_readings_are_routed() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let main = server
+            .mock_async(|when, then| {
+                when.path("/main");
+                then.status(200);
+            })
+            .await;
+
+        let quarantine = server
+            .mock_async(|when, then| {
+                when.path("/quarantine").body_contains("\"suspect\"");
+                then.status(200);
+            })
+            .await;
+
+        let gateway = plausibility_gateway(&server, "route");
+
+        let mut plausible = deserialize_file("../example-configs/test-sensor.json");
+        assert!(gateway.accept_reading(&mut plausible).is_ok());
+        gateway.handle_reading(plausible).await;
+
+        let mut implausible = implausible_reading();
+        assert!(gateway.accept_reading(&mut implausible).is_ok());
+        gateway.handle_reading(implausible).await;
+
+        main.assert_hits_async(1).await;
+        quarantine.assert_hits_async(1).await;
+    }
+
+    #[tokio::test]
+    async fn test_received_at_is_forwarded() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .body_contains("\"timestamp\":\"2023-07-25 14:30:13\"")
+                    .body_contains("\"received_at\":");
+                then.status(200);
+            })
+            .await;
+
+        let gateway = gateway_for(&server, "enabled: true");
+
+        let mut reading = deserialize_file("../example-configs/test-sensor.json");
+        reading.timestamp = String::from("2023-07-25 14:30:13");
+
+        assert!(gateway.accept_reading(&mut reading).is_ok());
+        gateway.handle_reading(reading).await;
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_open_circuit_diverts_readings_to_backlog() {
+        let server = httpmock::MockServer::start_async().await;
+        let path = std::env::temp_dir().join(format!("pixy-circuit-{}.jsonl", std::process::id()));
+
+        let _ = std::fs::remove_file(&path);
+
+        let failing = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST);
+                then.status(500);
+            })
+            .await;
+
+        let gateway = gateway_for(
+            &server,
+            &format!(
+                "circuitBreaker: {{ failureThreshold: 1, cooldown: 1, backlog: {{ path: '{}' }} }}",
+                path.display()
+            ),
+        );
+
+        let reading = deserialize_file("../example-configs/test-sensor.json");
+
+        gateway.handle_reading(reading.clone()).await;
+        gateway.handle_reading(reading.clone()).await;
+
+        failing.assert_hits_async(1).await;
+        assert!(gateway
+            .metrics()
+            .contains("\npixy_circuit_state{target=\"test\"} 1\n"));
+        assert!(gateway
+            .metrics()
+            .contains("\npixy_backlog_readings{target=\"test\"} 1\n"));
+
+        failing.delete_async().await;
+
+        let recovered = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST);
+                then.status(200);
+            })
+            .await;
+
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+        // The stored reading probes the target without waiting for a new reading
+        gateway.tick().await;
+
+        recovered.assert_hits_async(1).await;
+        assert!(!path.exists());
+
+        gateway.handle_reading(reading).await;
+
+        recovered.assert_hits_async(2).await;
+        assert!(gateway
+            .metrics()
+            .contains("\npixy_circuit_state{target=\"test\"} 0\n"));
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_failed_deliveries_become_dead_letters() {
+        let server = httpmock::MockServer::start_async().await;
+        let path =
+            std::env::temp_dir().join(format!("pixy-dead-letters-{}.jsonl", std::process::id()));
+
+        let _ = std::fs::remove_file(&path);
+
+        let failing = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST).path("/test");
+                then.status(500);
+            })
+            .await;
+
+        let dead_letters = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/dead")
+                    .body_contains("\"dead_letter\"")
+                    .body_contains("\"target\":\"test\"");
+                then.status(200);
+            })
+            .await;
+
+        let config = format!(
+            r#"
+            deadLetter:
+              path: "{}"
+              target: dead
+            targets:
+              - name: test
+                webhook:
+                  url: "{}"
+                  retries: 0
+              - name: dead
+                webhook:
+                  url: "{}"
+                  retries: 0
+            "#,
+            path.display(),
+            server.url("/test"),
+            server.url("/dead")
+        );
+
+        let gateway =
+            SensorGateway::try_from(serde_yaml::from_str::<ConfigFile>(&config).unwrap()).unwrap();
+
+        gateway
+            .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+            .await;
+
+        failing.assert_hits_async(1).await;
+        // The dead letter target does not receive the readings themselves
+        dead_letters.assert_hits_async(1).await;
+        assert!(gateway.metrics().contains("\npixy_dead_letters_total 1\n"));
+
+        let summary = gateway.replay_dead_letters(None).await.unwrap();
+
+        assert_eq!(
+            summary,
+            ReplaySummary {
+                replayed: 0,
+                failed: 1
+            }
+        );
+        failing.assert_hits_async(2).await;
+
+        failing.delete_async().await;
+
+        let recovered = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST).path("/test");
+                then.status(200);
+            })
+            .await;
+
+        // Replaying another target leaves the dead letter in the file
+        let summary = gateway.replay_dead_letters(Some("other")).await.unwrap();
+
+        assert_eq!(summary, ReplaySummary::default());
+
+        let summary = gateway.replay_dead_letters(Some("test")).await.unwrap();
+
+        assert_eq!(
+            summary,
+            ReplaySummary {
+                replayed: 1,
+                failed: 0
+            }
+        );
+        recovered.assert_hits_async(1).await;
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_readings_rejected_by_open_circuit_become_dead_letters() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let failing = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST).path("/test");
+                then.status(500);
+            })
+            .await;
+
+        let rejected = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/dead")
+                    .body_contains("\"error\":\"Circuit is open\"");
+                then.status(200);
+            })
+            .await;
+
+        let config = format!(
+            r#"
+            deadLetter:
+              target: dead
+            targets:
+              - name: test
+                circuitBreaker:
+                  failureThreshold: 1
+                  cooldown: 3600
+                webhook:
+                  url: "{}"
+                  retries: 0
+              - name: dead
+                webhook:
+                  url: "{}"
+                  retries: 0
+            "#,
+            server.url("/test"),
+            server.url("/dead")
+        );
+
+        let gateway =
+            SensorGateway::try_from(serde_yaml::from_str::<ConfigFile>(&config).unwrap()).unwrap();
+
+        for _ in 0..2 {
+            gateway
+                .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+                .await;
+        }
+
+        failing.assert_hits_async(1).await;
+        rejected.assert_hits_async(1).await;
+        assert!(gateway.metrics().contains("\npixy_dead_letters_total 2\n"));
+    }
+
+    #[tokio::test]
+    async fn test_readings_over_a_full_backlog_become_dead_letters() {
+        let server = httpmock::MockServer::start_async().await;
+        let backlog =
+            std::env::temp_dir().join(format!("pixy-full-backlog-{}.jsonl", std::process::id()));
+        let dead_letters =
+            std::env::temp_dir().join(format!("pixy-backlog-dead-{}.jsonl", std::process::id()));
+
+        let _ = std::fs::remove_file(&backlog);
+        let _ = std::fs::remove_file(&dead_letters);
+
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST);
+                then.status(500);
+            })
+            .await;
+
+        let config = format!(
+            r#"
+            deadLetter:
+              path: "{}"
+            targets:
+              - name: test
+                circuitBreaker:
+                  failureThreshold: 1
+                  cooldown: 3600
+                  backlog: {{ path: "{}", maxEntries: 1 }}
+                webhook:
+                  url: "{}"
+                  retries: 0
+            "#,
+            dead_letters.display(),
+            backlog.display(),
+            server.url("/")
+        );
+
+        let gateway =
+            SensorGateway::try_from(serde_yaml::from_str::<ConfigFile>(&config).unwrap()).unwrap();
+
+        for _ in 0..3 {
+            gateway
+                .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+                .await;
+        }
+
+        // The failed delivery and the reading the backlog had no room for
+        assert!(gateway.metrics().contains("\npixy_dead_letters_total 2\n"));
+        assert!(gateway
+            .metrics()
+            .contains("\npixy_backlog_readings{target=\"test\"} 1\n"));
+
+        let _ = std::fs::remove_file(&backlog);
+        let _ = std::fs::remove_file(&dead_letters);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_targets_are_skipped() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST);
+                then.status(200);
+            })
+            .await;
+
+        let gateway = gateway_for(&server, "enabled: false");
+        let reading = deserialize_file("../example-configs/test-sensor.json");
+
+        gateway.handle_reading(reading.clone()).await;
+
+        mock.assert_hits_async(0).await;
+        assert!(!gateway.targets()[0].enabled);
+
+        gateway.set_target_enabled("test", true).unwrap();
+        gateway.handle_reading(reading).await;
+
+        mock.assert_hits_async(1).await;
+
+        let status = &gateway.targets()[0];
+
+        assert!(status.enabled);
+        assert_eq!(status.deliveries, 1);
+        assert!(status.last_success.is_some());
+        assert!(gateway.set_target_enabled("unknown", true).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_target_receives_sample_reading() {
+        let server = httpmock::MockServer::start_async().await;
+
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .body_contains("\"uid\":\"pixy-test\"");
+                then.status(200);
+            })
+            .await;
+
+        let gateway = gateway_for(&server, "enabled: true");
+
+        gateway.test_target("test").await.unwrap();
+
+        mock.assert_hits_async(1).await;
+        assert!(gateway.test_target("unknown").await.is_err());
+        // Test deliveries are not counted as deliveries of the target
+        assert_eq!(gateway.targets()[0].deliveries, 0);
+    }
+
+    #[tokio::test]
+    async fn test_latest_reading_of_each_device_is_kept() {
+        let server = httpmock::MockServer::start_async().await;
+        let gateway = gateway_for(&server, "enabled: false");
+
+        let mut reading = deserialize_file("../example-configs/test-sensor.json");
+        reading.received_at = Some(Utc::now());
+
+        gateway.handle_reading(SensorMessage::sample()).await;
+        gateway.handle_reading(reading.clone()).await;
+        gateway.handle_reading(reading).await;
+
+        let devices = gateway.devices();
+
+        // Only the boards in `devices` are kept when some are configured
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].nickname(), "office");
+        assert!(devices[0].derived.is_some());
+        assert!(devices[0].received_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_latest_readings_are_bounded_without_devices() {
+        let gateway =
+            SensorGateway::try_from(serde_yaml::from_str::<ConfigFile>("targets: []").unwrap())
+                .unwrap();
+
+        let start = Utc::now();
+        for i in 0..=MAX_LATEST_DEVICES {
+            let mut reading = SensorMessage::sample();
+            reading.metadata.uid = format!("board-{}", i);
+            reading.received_at = Some(start + chrono::Duration::seconds(i as i64));
+            gateway.handle_reading(reading).await;
+        }
+
+        let devices = gateway.devices();
+
+        // The board that was seen the longest ago makes room for the new one
+        assert_eq!(devices.len(), MAX_LATEST_DEVICES);
+        assert!(!devices
+            .iter()
+            .any(|device| device.metadata.uid == "board-0"));
+        assert!(devices
+            .iter()
+            .any(|device| device.metadata.uid == format!("board-{}", MAX_LATEST_DEVICES)));
+    }
+
+    #[tokio::test]
+    async fn test_latest_readings_are_carried_over() {
+        let server = httpmock::MockServer::start_async().await;
+        let previous = gateway_for(&server, "enabled: false");
+        previous
+            .handle_reading(deserialize_file("../example-configs/test-sensor.json"))
+            .await;
+
+        let gateway = gateway_for(&server, "enabled: false");
+        gateway.carry_over(&previous);
+
+        let devices = gateway.devices();
+
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].nickname(), "office");
+    }
 }
@@ -0,0 +1,255 @@
+//! Counters describing the activity of the gateway.
+//!
+//! The metrics are rendered in the Prometheus text exposition format, so that they
+//! can be scraped by most monitoring systems. They are also recorded through the
+//! OpenTelemetry API, and exported when a meter provider is installed.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use opentelemetry::{global, metrics::Counter};
+
+#[derive(Debug, Default)]
+pub struct GatewayMetrics {
+    readings_received: AtomicU64,
+    duplicates_suppressed: AtomicU64,
+    implausible_readings: AtomicU64,
+    invalid_signatures: AtomicU64,
+    circuit_rejections: AtomicU64,
+    dead_letters: AtomicU64,
+    otel: OtelCounters,
+}
+
+/// The counters recorded through OpenTelemetry. They do nothing unless a meter
+/// provider was installed when the gateway was created.
+#[derive(Debug)]
+struct OtelCounters {
+    readings_received: Counter<u64>,
+    duplicates_suppressed: Counter<u64>,
+    implausible_readings: Counter<u64>,
+    invalid_signatures: Counter<u64>,
+    circuit_rejections: Counter<u64>,
+    dead_letters: Counter<u64>,
+}
+
+impl Default for OtelCounters {
+    fn default() -> Self {
+        let meter = global::meter("pixy");
+        let counter = |name: &'static str, description: &'static str| {
+            meter
+                .u64_counter(name)
+                .with_description(description)
+                .build()
+        };
+
+        Self {
+            readings_received: counter(
+                "pixy.readings.received",
+                "The number of readings received by the gateway",
+            ),
+            duplicates_suppressed: counter(
+                "pixy.readings.duplicate",
+                "The number of duplicate readings that were not relayed",
+            ),
+            implausible_readings: counter(
+                "pixy.readings.implausible",
+                "The number of readings that failed the plausibility checks",
+            ),
+            invalid_signatures: counter(
+                "pixy.signatures.invalid",
+                "The number of payloads rejected because of an invalid signature",
+            ),
+            circuit_rejections: counter(
+                "pixy.circuit.rejected_deliveries",
+                "The number of deliveries not made because the circuit of the target was open",
+            ),
+            dead_letters: counter(
+                "pixy.dead_letters",
+                "The number of readings that could not be delivered and became dead letters",
+            ),
+        }
+    }
+}
+
+/// Writes a single metric, along with its help text and type.
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Writes a metric with a value for each target, labelled with the name of the target.
+pub(crate) fn write_target_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    values: &[(&str, u64)],
+) {
+    if values.is_empty() {
+        return;
+    }
+
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+
+    for (target, value) in values {
+        let target = target.replace('\\', "\\\\").replace('"', "\\\"");
+        let _ = writeln!(out, "{}{{target=\"{}\"}} {}", name, target, value);
+    }
+}
+
+impl GatewayMetrics {
+    pub(crate) fn reading_received(&self) {
+        self.readings_received.fetch_add(1, Ordering::Relaxed);
+        self.otel.readings_received.add(1, &[]);
+    }
+
+    pub(crate) fn duplicate_suppressed(&self) {
+        self.duplicates_suppressed.fetch_add(1, Ordering::Relaxed);
+        self.otel.duplicates_suppressed.add(1, &[]);
+    }
+
+    pub(crate) fn implausible_reading(&self) {
+        self.implausible_readings.fetch_add(1, Ordering::Relaxed);
+        self.otel.implausible_readings.add(1, &[]);
+    }
+
+    pub(crate) fn invalid_signature(&self) {
+        self.invalid_signatures.fetch_add(1, Ordering::Relaxed);
+        self.otel.invalid_signatures.add(1, &[]);
+    }
+
+    pub(crate) fn circuit_rejection(&self) {
+        self.circuit_rejections.fetch_add(1, Ordering::Relaxed);
+        self.otel.circuit_rejections.add(1, &[]);
+    }
+
+    pub(crate) fn dead_letter(&self) {
+        self.dead_letters.fetch_add(1, Ordering::Relaxed);
+        self.otel.dead_letters.add(1, &[]);
+    }
+
+    /// The number of readings received by the gateway.
+    pub fn readings_received(&self) -> u64 {
+        self.readings_received.load(Ordering::Relaxed)
+    }
+
+    /// The number of readings that were not relayed because they were duplicates.
+    pub fn duplicates_suppressed(&self) -> u64 {
+        self.duplicates_suppressed.load(Ordering::Relaxed)
+    }
+
+    /// The number of readings that failed the plausibility checks.
+    pub fn implausible_readings(&self) -> u64 {
+        self.implausible_readings.load(Ordering::Relaxed)
+    }
+
+    /// The number of payloads rejected because their signature could not be verified.
+    pub fn invalid_signatures(&self) -> u64 {
+        self.invalid_signatures.load(Ordering::Relaxed)
+    }
+
+    /// The number of deliveries that were not made because the circuit of their
+    /// target was open.
+    pub fn circuit_rejections(&self) -> u64 {
+        self.circuit_rejections.load(Ordering::Relaxed)
+    }
+
+    /// The number of readings that could not be delivered to a target, and were
+    /// sent to the dead letter destinations.
+    pub fn dead_letters(&self) -> u64 {
+        self.dead_letters.load(Ordering::Relaxed)
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        write_metric(
+            &mut out,
+            "pixy_readings_received_total",
+            "counter",
+            "The number of readings received by the gateway",
+            self.readings_received(),
+        );
+
+        write_metric(
+            &mut out,
+            "pixy_duplicate_readings_total",
+            "counter",
+            "The number of duplicate readings that were not relayed",
+            self.duplicates_suppressed(),
+        );
+
+        write_metric(
+            &mut out,
+            "pixy_implausible_readings_total",
+            "counter",
+            "The number of readings that failed the plausibility checks",
+            self.implausible_readings(),
+        );
+
+        write_metric(
+            &mut out,
+            "pixy_invalid_signatures_total",
+            "counter",
+            "The number of payloads rejected because of an invalid signature",
+            self.invalid_signatures(),
+        );
+
+        write_metric(
+            &mut out,
+            "pixy_circuit_rejected_deliveries_total",
+            "counter",
+            "The number of deliveries not made because the circuit of the target was open",
+            self.circuit_rejections(),
+        );
+
+        write_metric(
+            &mut out,
+            "pixy_dead_letters_total",
+            "counter",
+            "The number of readings that could not be delivered and were sent to the dead letter destinations",
+            self.dead_letters(),
+        );
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = GatewayMetrics::default();
+
+        metrics.reading_received();
+        metrics.reading_received();
+        metrics.duplicate_suppressed();
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains("# TYPE pixy_readings_received_total counter\n"));
+        assert!(rendered.contains("\npixy_readings_received_total 2\n"));
+        assert!(rendered.contains("\npixy_duplicate_readings_total 1\n"));
+    }
+
+    #[test]
+    fn test_render_target_metric() {
+        let mut out = String::new();
+
+        write_target_metric(
+            &mut out,
+            "pixy_circuit_state",
+            "gauge",
+            "The state of the circuit",
+            &[("first", 0), ("say \"hi\"", 1)],
+        );
+
+        assert!(out.contains("\npixy_circuit_state{target=\"first\"} 0\n"));
+        assert!(out.contains("\npixy_circuit_state{target=\"say \\\"hi\\\"\"} 1\n"));
+    }
+}
@@ -0,0 +1,217 @@
+//! Plausibility checks for the values sent by the sensors.
+//!
+//! A glitching sensor can report values that are physically impossible, or that
+//! change faster than the quantity being measured ever could. Each field of the
+//! readings can be given a valid range and a maximum rate of change per minute,
+//! which is compared against the last plausible reading of the same device.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::PlausibilityConfig;
+use crate::{Readings, SensorMessage};
+
+/// The fields of the readings that can be checked.
+pub const FIELDS: [&str; 7] = [
+    "temperature",
+    "pressure",
+    "humidity",
+    "color_temperature",
+    "gas_resistance",
+    "aqi",
+    "luminance",
+];
+
+/// Describes why a reading was considered implausible.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Suspect {
+    /// A description of every check that the reading failed.
+    pub violations: Vec<String>,
+}
+
+fn field_value(readings: &Readings, field: &str) -> Option<f64> {
+    match field {
+        "temperature" => Some(readings.temperature as f64),
+        "pressure" => Some(readings.pressure as f64),
+        "humidity" => Some(readings.humidity as f64),
+        "color_temperature" => readings.color_temperature.map(|v| v as f64),
+        "gas_resistance" => readings.gas_resistance.map(|v| v as f64),
+        "aqi" => readings.aqi.map(|v| v as f64),
+        "luminance" => readings.luminance.map(|v| v as f64),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PlausibilityChecker {
+    pub(crate) config: PlausibilityConfig,
+    /// The last plausible value of each field, per device.
+    last: Mutex<HashMap<String, (Instant, Readings)>>,
+}
+
+impl PlausibilityChecker {
+    pub(crate) fn new(config: &PlausibilityConfig) -> Self {
+        Self {
+            config: config.clone(),
+            last: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks the readings of a message, returning the violations found. The
+    /// message is remembered for rate-of-change checks only if it is plausible.
+    pub(crate) fn check(&self, message: &SensorMessage, now: Instant) -> Option<Suspect> {
+        let mut last = self.last.lock().expect("plausibility lock poisoned");
+        let previous = last.get(&message.metadata.uid);
+
+        let mut violations = Vec::new();
+
+        for field in FIELDS {
+            let Some(value) = field_value(&message.readings, field) else {
+                continue;
+            };
+
+            if !value.is_finite() {
+                violations.push(format!("{} is not a finite number", field));
+                continue;
+            }
+
+            let Some(limits) = self.config.fields.get(field) else {
+                continue;
+            };
+
+            if let Some(min) = limits.min.filter(|&min| value < min) {
+                violations.push(format!(
+                    "{} {} is below the minimum of {}",
+                    field, value, min
+                ));
+            }
+
+            if let Some(max) = limits.max.filter(|&max| value > max) {
+                violations.push(format!(
+                    "{} {} is above the maximum of {}",
+                    field, value, max
+                ));
+            }
+
+            let Some(max_rate) = limits.max_rate else {
+                continue;
+            };
+
+            let Some((seen_at, readings)) = previous else {
+                continue;
+            };
+
+            let Some(last_value) = field_value(readings, field) else {
+                continue;
+            };
+
+            // Readings that arrive together are compared as if a second apart, so
+            // that a retried upload cannot divide by zero
+            let minutes = (now.duration_since(*seen_at).as_secs_f64() / 60.0).max(1.0 / 60.0);
+            let rate = (value - last_value).abs() / minutes;
+
+            if rate > max_rate {
+                violations.push(format!(
+                    "{} changed by {:.2} per minute, above the maximum of {}",
+                    field, rate, max_rate
+                ));
+            }
+        }
+
+        if violations.is_empty() {
+            last.insert(
+                message.metadata.uid.clone(),
+                (now, message.readings.clone()),
+            );
+
+            None
+        } else {
+            Some(Suspect { violations })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const TEST_MESSAGE: &str = include_str!("../../example-configs/test-sensor.json");
+
+    fn message(temperature: f32) -> SensorMessage {
+        let mut message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+
+        message.readings.temperature = temperature;
+
+        message
+    }
+
+    fn checker(config: &str) -> PlausibilityChecker {
+        PlausibilityChecker::new(&serde_yaml::from_str(config).unwrap())
+    }
+
+    #[test]
+    fn test_values_in_range_are_plausible() {
+        let checker = checker("fields: { temperature: { min: -40, max: 85 } }");
+
+        assert!(checker.check(&message(28.0), Instant::now()).is_none());
+    }
+
+    #[test]
+    fn test_values_out_of_range_are_suspect() {
+        let checker = checker("fields: { temperature: { min: -40, max: 85 } }");
+
+        let suspect = checker.check(&message(850.0), Instant::now()).unwrap();
+
+        assert_eq!(
+            suspect.violations,
+            vec!["temperature 850 is above the maximum of 85"]
+        );
+
+        let suspect = checker.check(&message(-50.0), Instant::now()).unwrap();
+
+        assert_eq!(
+            suspect.violations,
+            vec!["temperature -50 is below the minimum of -40"]
+        );
+    }
+
+    #[test]
+    fn test_non_finite_values_are_always_suspect() {
+        let checker = checker("{}");
+
+        assert!(checker
+            .check(&message(f32::INFINITY), Instant::now())
+            .is_some());
+        assert!(checker.check(&message(f32::NAN), Instant::now()).is_some());
+    }
+
+    #[test]
+    fn test_rate_of_change_is_checked_per_device() {
+        let checker = checker("fields: { temperature: { maxRate: 1 } }");
+        let start = Instant::now();
+
+        assert!(checker.check(&message(20.0), start).is_none());
+        assert!(checker
+            .check(&message(21.5), start + Duration::from_secs(120))
+            .is_none());
+        assert!(checker
+            .check(&message(30.0), start + Duration::from_secs(180))
+            .is_some());
+
+        // The suspect reading is not used as the baseline
+        assert!(checker
+            .check(&message(22.0), start + Duration::from_secs(240))
+            .is_none());
+
+        let mut other = message(30.0);
+        other.metadata.uid = "other".to_string();
+
+        assert!(checker
+            .check(&other, start + Duration::from_secs(240))
+            .is_none());
+    }
+}
@@ -0,0 +1,350 @@
+//! Rate limiting of the deliveries made to a target.
+//!
+//! The overall rate of a target is enforced with the generic cell rate algorithm
+//! (a token bucket that can hand out reservations for future slots), and the
+//! optional minimum interval between deliveries is tracked for each device.
+//!
+//! Deliveries that wait for a slot are bounded by `maxQueue` when queueing, and by
+//! the number of devices when coalescing. When the gateway is flushed, before it is
+//! replaced or stopped, the waiting deliveries are sent without waiting, and so
+//! is every later delivery.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use minijinja::value::Value;
+use tokio::sync::watch;
+
+use crate::config::{OnLimit, RateLimitConfig};
+use crate::SensorMessage;
+
+/// What the gateway should do with a delivery that was checked against the limiter.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Decision {
+    /// The delivery can be sent immediately.
+    Send,
+    /// The delivery has a reserved slot, and should be sent after waiting.
+    Wait(Duration),
+    /// The delivery replaced a pending delivery for the same device.
+    Coalesced,
+    /// The delivery is over the limit and should be discarded.
+    Drop,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The theoretical arrival time of the next delivery.
+    tat: Option<Instant>,
+    /// The earliest time that each device can be delivered again.
+    devices: HashMap<String, Instant>,
+    /// The number of deliveries waiting for a reserved slot.
+    queued: usize,
+    /// The latest message of each device waiting for a reserved slot, when coalescing.
+    pending: HashMap<String, (SensorMessage, Value)>,
+}
+
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    state: Mutex<State>,
+    /// Whether the limiter was flushed, after which deliveries no longer wait.
+    flushed: watch::Sender<bool>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            config: config.clone(),
+            state: Mutex::new(State::default()),
+            flushed: watch::Sender::new(false),
+        }
+    }
+
+    /// The interval between deliveries at the sustained rate, and the number of
+    /// intervals that can be spent ahead of time.
+    fn emission(&self) -> Option<(Duration, Duration)> {
+        let max_requests = self.config.max_requests.filter(|&n| n > 0)?;
+
+        let interval = Duration::from_secs_f64(self.config.period as f64 / max_requests as f64);
+        let burst = self.config.burst.unwrap_or(max_requests).max(1);
+
+        Some((interval, interval * (burst - 1)))
+    }
+
+    /// Returns the earliest time that a delivery for the device can be sent.
+    fn earliest(&self, state: &State, uid: &str, now: Instant) -> Instant {
+        let mut earliest = now;
+
+        if let (Some((_, tolerance)), Some(tat)) = (self.emission(), state.tat) {
+            earliest = earliest.max(tat.checked_sub(tolerance).unwrap_or(now));
+        }
+
+        if let Some(&next) = state.devices.get(uid) {
+            earliest = earliest.max(next);
+        }
+
+        earliest
+    }
+
+    /// Spends the slot at the given time.
+    fn reserve(&self, state: &mut State, uid: &str, at: Instant) {
+        if let Some((interval, _)) = self.emission() {
+            state.tat = Some(state.tat.unwrap_or(at).max(at) + interval);
+        }
+
+        if let Some(min_interval) = self.config.min_interval {
+            state
+                .devices
+                .insert(uid.to_string(), at + Duration::from_secs(min_interval));
+        }
+    }
+
+    /// Checks a delivery against the limits of the target, reserving a slot for it
+    /// if it can be sent now or later.
+    pub(crate) fn check(&self, message: &SensorMessage, context: &Value, now: Instant) -> Decision {
+        let mut state = self.state.lock().expect("rate limiter lock poisoned");
+        let uid = message.metadata.uid.as_str();
+
+        if self.config.on_limit == OnLimit::Coalesce {
+            if let Some(pending) = state.pending.get_mut(uid) {
+                *pending = (message.clone(), context.clone());
+                return Decision::Coalesced;
+            }
+        }
+
+        let earliest = self.earliest(&state, uid, now);
+
+        if earliest <= now || *self.flushed.borrow() {
+            self.reserve(&mut state, uid, now);
+            return Decision::Send;
+        }
+
+        match self.config.on_limit {
+            OnLimit::Drop => Decision::Drop,
+            OnLimit::Queue if state.queued >= self.config.max_queue => Decision::Drop,
+            OnLimit::Queue => {
+                state.queued += 1;
+                self.reserve(&mut state, uid, earliest);
+                Decision::Wait(earliest - now)
+            }
+            OnLimit::Coalesce => {
+                state
+                    .pending
+                    .insert(uid.to_string(), (message.clone(), context.clone()));
+                self.reserve(&mut state, uid, earliest);
+                Decision::Wait(earliest - now)
+            }
+        }
+    }
+
+    /// Waits for the slot of a delivery, or until the limiter is flushed.
+    pub(crate) async fn wait(&self, delay: Duration) {
+        let mut flushed = self.flushed.subscribe();
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = flushed.wait_for(|flushed| *flushed) => {}
+        }
+    }
+
+    /// Stops deliveries from waiting for their slot, including those that are
+    /// already waiting.
+    pub(crate) fn flush(&self) {
+        self.flushed.send_replace(true);
+    }
+
+    /// Completes a delivery that waited for its slot, returning the latest message
+    /// of the device if deliveries are coalesced.
+    pub(crate) fn release(&self, uid: &str) -> Option<(SensorMessage, Value)> {
+        let mut state = self.state.lock().expect("rate limiter lock poisoned");
+
+        match self.config.on_limit {
+            OnLimit::Coalesce => state.pending.remove(uid),
+            _ => {
+                state.queued = state.queued.saturating_sub(1);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minijinja::context;
+
+    const TEST_MESSAGE: &str = include_str!("../../example-configs/test-sensor.json");
+
+    fn message(uid: &str) -> SensorMessage {
+        let mut message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+
+        message.metadata.uid = uid.to_string();
+
+        message
+    }
+
+    fn limiter(config: &str) -> RateLimiter {
+        RateLimiter::new(&serde_yaml::from_str(config).unwrap())
+    }
+
+    #[test]
+    fn test_drops_over_the_limit() {
+        let limiter = limiter("{ maxRequests: 2, period: 60 }");
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Send
+        );
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Send
+        );
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Drop
+        );
+
+        // One request is replenished every 30 seconds
+        let later = now + Duration::from_secs(30);
+
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), later),
+            Decision::Send
+        );
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), later),
+            Decision::Drop
+        );
+    }
+
+    #[test]
+    fn test_burst_limits_requests_made_at_once() {
+        let limiter = limiter("{ maxRequests: 10, period: 60, burst: 1 }");
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Send
+        );
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Drop
+        );
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now + Duration::from_secs(6)),
+            Decision::Send
+        );
+    }
+
+    #[test]
+    fn test_min_interval_is_per_device() {
+        let limiter = limiter("{ minInterval: 60 }");
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Send
+        );
+        assert_eq!(
+            limiter.check(&message("b"), &context!(), now),
+            Decision::Send
+        );
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Drop
+        );
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now + Duration::from_secs(60)),
+            Decision::Send
+        );
+    }
+
+    #[test]
+    fn test_queue_reserves_future_slots() {
+        let limiter = limiter("{ maxRequests: 1, period: 10, onLimit: queue, maxQueue: 2 }");
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Send
+        );
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Wait(Duration::from_secs(10))
+        );
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Wait(Duration::from_secs(20))
+        );
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Drop
+        );
+
+        assert!(limiter.release("a").is_none());
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Wait(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn test_coalesce_keeps_latest_message() {
+        let limiter = limiter("{ minInterval: 60, onLimit: coalesce }");
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Send
+        );
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Wait(Duration::from_secs(60))
+        );
+
+        let mut latest = message("a");
+        latest.timestamp = "latest".to_string();
+
+        assert_eq!(
+            limiter.check(&latest, &context!(), now),
+            Decision::Coalesced
+        );
+
+        let (released, _) = limiter.release("a").unwrap();
+
+        assert_eq!(released.timestamp, "latest");
+    }
+
+    #[tokio::test]
+    async fn test_flush_releases_waiting_deliveries() {
+        let limiter = limiter("{ maxRequests: 1, period: 3600, onLimit: queue }");
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Send
+        );
+
+        let Decision::Wait(delay) = limiter.check(&message("a"), &context!(), now) else {
+            panic!("the delivery should wait for its slot");
+        };
+
+        let waiting = async {
+            limiter.wait(delay).await;
+            limiter.release("a");
+        };
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(waiting, async { limiter.flush() })
+        })
+        .await
+        .expect("flushing should release the waiting delivery");
+
+        assert_eq!(
+            limiter.check(&message("a"), &context!(), now),
+            Decision::Send
+        );
+    }
+}
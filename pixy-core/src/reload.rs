@@ -0,0 +1,194 @@
+//! A gateway that can load its configuration file again while it is running.
+//!
+//! Reloading builds a new gateway from the file and swaps it in. Readings that are
+//! being handled finish with the gateway they started with, and the readings held
+//! back by the previous gateway, such as open aggregation windows, are flushed.
+//! Targets that are still configured keep whether an admin enabled or disabled
+//! them and the results of their deliveries, matched by name, and the latest
+//! reading of each board is kept. The rest of the state, such as the metrics and
+//! circuits, is not carried over.
+
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::status::TargetStatus;
+use crate::validation::parse_configs;
+use crate::{Gateway, SensorGateway, SensorMessage, READINGS_CAPACITY};
+
+#[derive(Debug)]
+pub struct ReloadableGateway {
+    config_file: String,
+    gateway: RwLock<Arc<SensorGateway>>,
+    /// The readings of every gateway that is swapped in.
+    readings: broadcast::Sender<SensorMessage>,
+}
+
+impl ReloadableGateway {
+    /// Creates a gateway from a configuration file.
+    ///
+    /// ## Arguments
+    ///
+    /// * `config_file` - The path of the configuration file, which is read again
+    ///   on every reload.
+    pub fn new(config_file: &str) -> Result<Self, String> {
+        let readings = broadcast::channel(READINGS_CAPACITY).0;
+        let gateway =
+            SensorGateway::try_from(parse_configs(config_file)?)?.with_readings(readings.clone());
+
+        Ok(Self {
+            config_file: config_file.to_string(),
+            gateway: RwLock::new(Arc::new(gateway)),
+            readings,
+        })
+    }
+
+    /// The gateway currently in use.
+    fn current(&self) -> Arc<SensorGateway> {
+        self.gateway.read().expect("gateway lock poisoned").clone()
+    }
+}
+
+#[async_trait]
+impl Gateway for ReloadableGateway {
+    fn verify_payload(&self, headers: &HeaderMap, body: &[u8]) -> Result<(), String> {
+        self.current().verify_payload(headers, body)
+    }
+
+    fn accept_reading(&self, reading: &mut SensorMessage) -> Result<(), String> {
+        self.current().accept_reading(reading)
+    }
+
+    async fn handle_reading(&self, reading: SensorMessage) {
+        // The reading is counted before the lock is released, so that a reload
+        // cannot drain the gateway before the reading is added to its windows
+        let (gateway, _in_flight) = {
+            let gateway = self.gateway.read().expect("gateway lock poisoned");
+            (gateway.clone(), gateway.start_reading())
+        };
+
+        gateway.handle_reading(reading).await
+    }
+
+    fn metrics(&self) -> String {
+        self.current().metrics()
+    }
+
+    fn targets(&self) -> Vec<TargetStatus> {
+        self.current().targets()
+    }
+
+    fn set_target_enabled(&self, name: &str, enabled: bool) -> Result<(), String> {
+        self.current().set_target_enabled(name, enabled)
+    }
+
+    async fn test_target(&self, name: &str) -> Result<(), String> {
+        self.current().test_target(name).await
+    }
+
+    fn devices(&self) -> Vec<SensorMessage> {
+        self.current().devices()
+    }
+
+    fn subscribe(&self) -> Option<broadcast::Receiver<SensorMessage>> {
+        Some(self.readings.subscribe())
+    }
+
+    fn loaded_at(&self) -> Option<DateTime<Utc>> {
+        self.current().loaded_at()
+    }
+
+    async fn tick(&self) {
+        self.current().tick().await
+    }
+
+    async fn flush(&self) {
+        self.current().flush().await
+    }
+
+    async fn reload(&self) -> Result<(), String> {
+        let gateway = SensorGateway::try_from(parse_configs(&self.config_file)?)?
+            .with_readings(self.readings.clone());
+
+        gateway.carry_over(&self.current());
+
+        let previous = std::mem::replace(
+            &mut *self.gateway.write().expect("gateway lock poisoned"),
+            Arc::new(gateway),
+        );
+
+        info!(config_file = self.config_file, "Reloaded configuration");
+
+        previous.flush().await;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reload_picks_up_config_changes() {
+        let path = std::env::temp_dir().join(format!("pixy-reload-{}.yaml", std::process::id()));
+        let config = |name: &str| {
+            format!(
+                "targets:\n  - name: {}\n    webhook:\n      url: http://localhost:9147/echo\n",
+                name
+            )
+        };
+
+        std::fs::write(&path, config("before")).unwrap();
+
+        let gateway = ReloadableGateway::new(&path.to_string_lossy()).unwrap();
+
+        assert_eq!(gateway.targets()[0].name, "before");
+
+        std::fs::write(&path, config("after")).unwrap();
+        gateway.reload().await.unwrap();
+
+        assert_eq!(gateway.targets()[0].name, "after");
+
+        // An invalid file keeps the current configuration
+        std::fs::write(&path, "targets: 42").unwrap();
+
+        assert!(gateway.reload().await.is_err());
+        assert_eq!(gateway.targets()[0].name, "after");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_disabled_targets() {
+        let path =
+            std::env::temp_dir().join(format!("pixy-reload-state-{}.yaml", std::process::id()));
+        let config = |url: &str| {
+            format!(
+                "targets:\n  - name: influx\n    webhook:\n      url: {}\n  - name: echo\n    webhook:\n      url: http://localhost:9147/echo\n",
+                url
+            )
+        };
+
+        std::fs::write(&path, config("http://localhost:8086")).unwrap();
+
+        let gateway = ReloadableGateway::new(&path.to_string_lossy()).unwrap();
+
+        gateway.set_target_enabled("influx", false).unwrap();
+
+        std::fs::write(&path, config("http://localhost:8087")).unwrap();
+        gateway.reload().await.unwrap();
+
+        let targets = gateway.targets();
+
+        assert!(!targets[0].enabled);
+        assert!(targets[0].config.contains("8087"));
+        assert!(targets[1].enabled);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
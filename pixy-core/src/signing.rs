@@ -0,0 +1,212 @@
+//! HMAC signatures for the payloads exchanged with Pixy.
+//!
+//! Signatures are sent as `<algorithm>=<hex digest>`, the same way GitHub and
+//! Stripe sign their webhooks. The signed content is either the raw body, or the
+//! Unix timestamp of the request and the body joined by a `.`, which lets the
+//! receiver reject requests that are replayed later on.
+
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Sha512};
+
+use crate::config::{CanonicalFormat, SigningAlgorithm};
+
+/// The header that signatures are sent in, unless configured otherwise.
+pub const DEFAULT_SIGNATURE_HEADER: &str = "X-Pixy-Signature";
+
+/// The header that the timestamp of a signature is sent in, unless configured otherwise.
+pub const DEFAULT_TIMESTAMP_HEADER: &str = "X-Pixy-Timestamp";
+
+/// The header that identifies the key a payload was signed with, unless configured otherwise.
+pub const DEFAULT_KEY_ID_HEADER: &str = "X-Pixy-Key-Id";
+
+impl SigningAlgorithm {
+    fn prefix(&self) -> &'static str {
+        match self {
+            SigningAlgorithm::Sha256 => "sha256",
+            SigningAlgorithm::Sha512 => "sha512",
+        }
+    }
+}
+
+/// Builds the content that is signed for a request.
+fn canonical_content(format: CanonicalFormat, timestamp: i64, body: &[u8]) -> Vec<u8> {
+    match format {
+        CanonicalFormat::Body => body.to_vec(),
+        CanonicalFormat::TimestampBody => {
+            let mut content = format!("{}.", timestamp).into_bytes();
+            content.extend_from_slice(body);
+            content
+        }
+    }
+}
+
+fn digest(algorithm: SigningAlgorithm, secret: &[u8], content: &[u8]) -> Vec<u8> {
+    match algorithm {
+        SigningAlgorithm::Sha256 => {
+            let mut mac =
+                Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+            mac.update(content);
+            mac.finalize().into_bytes().to_vec()
+        }
+        SigningAlgorithm::Sha512 => {
+            let mut mac =
+                Hmac::<Sha512>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+            mac.update(content);
+            mac.finalize().into_bytes().to_vec()
+        }
+    }
+}
+
+/// Signs a request body, returning the value of the signature header.
+///
+/// ## Arguments
+///
+/// * `algorithm` - The hash algorithm to use.
+/// * `format` - The content that is signed.
+/// * `secret` - The shared secret.
+/// * `timestamp` - The Unix timestamp of the request, in seconds.
+/// * `body` - The raw body of the request.
+pub fn sign(
+    algorithm: SigningAlgorithm,
+    format: CanonicalFormat,
+    secret: &[u8],
+    timestamp: i64,
+    body: &[u8],
+) -> String {
+    let content = canonical_content(format, timestamp, body);
+
+    format!(
+        "{}={}",
+        algorithm.prefix(),
+        hex::encode(digest(algorithm, secret, &content))
+    )
+}
+
+/// Compares two byte strings in constant time, so that the time taken does not
+/// reveal how much of a signature or token was correct.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Checks the value of a signature header against a request body.
+///
+/// ## Arguments
+///
+/// * `algorithm` - The hash algorithm the signature should use.
+/// * `format` - The content that is signed.
+/// * `secret` - The shared secret.
+/// * `timestamp` - The Unix timestamp of the request, in seconds.
+/// * `body` - The raw body of the request.
+/// * `signature` - The value of the signature header.
+pub fn verify(
+    algorithm: SigningAlgorithm,
+    format: CanonicalFormat,
+    secret: &[u8],
+    timestamp: i64,
+    body: &[u8],
+    signature: &str,
+) -> bool {
+    let Some(provided) = signature
+        .trim()
+        .strip_prefix(algorithm.prefix())
+        .and_then(|rest| rest.strip_prefix('='))
+        .and_then(|digest| hex::decode(digest).ok())
+    else {
+        return false;
+    };
+
+    let content = canonical_content(format, timestamp, body);
+
+    constant_time_eq(&digest(algorithm, secret, &content), &provided)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_body() {
+        // echo -n 'hello' | openssl dgst -sha256 -hmac 'secret'
+        let signature = sign(
+            SigningAlgorithm::Sha256,
+            CanonicalFormat::Body,
+            b"secret",
+            0,
+            b"hello",
+        );
+
+        assert_eq!(
+            signature,
+            "sha256=88aab3ede8d3adf94d26ab90d3bafd4a2083070c3bcce9c014ee04a443847c0b"
+        );
+    }
+
+    #[test]
+    fn test_sign_timestamp_and_body() {
+        let with_timestamp = sign(
+            SigningAlgorithm::Sha256,
+            CanonicalFormat::TimestampBody,
+            b"secret",
+            1690295413,
+            b"hello",
+        );
+
+        let expected = sign(
+            SigningAlgorithm::Sha256,
+            CanonicalFormat::Body,
+            b"secret",
+            0,
+            b"1690295413.hello",
+        );
+
+        assert_eq!(with_timestamp, expected);
+    }
+
+    #[test]
+    fn test_sign_sha512() {
+        let signature = sign(
+            SigningAlgorithm::Sha512,
+            CanonicalFormat::Body,
+            b"secret",
+            0,
+            b"hello",
+        );
+
+        assert!(signature.starts_with("sha512="));
+        assert_eq!(signature.len(), "sha512=".len() + 128);
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let signature = sign(
+            SigningAlgorithm::Sha256,
+            CanonicalFormat::TimestampBody,
+            b"secret",
+            1690295413,
+            b"hello",
+        );
+
+        let verify_with = |secret: &[u8], timestamp: i64, body: &[u8], signature: &str| {
+            verify(
+                SigningAlgorithm::Sha256,
+                CanonicalFormat::TimestampBody,
+                secret,
+                timestamp,
+                body,
+                signature,
+            )
+        };
+
+        assert!(verify_with(b"secret", 1690295413, b"hello", &signature));
+        assert!(!verify_with(b"other", 1690295413, b"hello", &signature));
+        assert!(!verify_with(b"secret", 1690295414, b"hello", &signature));
+        assert!(!verify_with(b"secret", 1690295413, b"hello!", &signature));
+        assert!(!verify_with(b"secret", 1690295413, b"hello", "sha256=zz"));
+        assert!(!verify_with(
+            b"secret",
+            1690295413,
+            b"hello",
+            &signature.replace("sha256", "sha512")
+        ));
+    }
+}
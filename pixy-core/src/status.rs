@@ -0,0 +1,126 @@
+//! The status of the targets of the gateway, for inspecting them at runtime.
+
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::DeliveryError;
+
+/// A snapshot of the state of a target.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetStatus {
+    /// The name of the target.
+    pub name: String,
+
+    /// Whether readings are delivered to the target.
+    pub enabled: bool,
+
+    /// The settings of the target, with its secrets redacted.
+    pub config: String,
+
+    /// The state of the circuit of the target, if it has a circuit breaker.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit: Option<String>,
+
+    /// The number of readings waiting in the backlog of the target, if it has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backlog: Option<usize>,
+
+    /// The number of deliveries made to the target.
+    pub deliveries: u64,
+
+    /// The number of deliveries that failed.
+    pub failures: u64,
+
+    /// When a delivery last succeeded.
+    pub last_success: Option<DateTime<Utc>>,
+
+    /// When a delivery last failed.
+    pub last_failure: Option<DateTime<Utc>>,
+
+    /// The error of the last failed delivery.
+    pub last_error: Option<String>,
+}
+
+impl TargetStatus {
+    /// Whether the target is enabled but cannot be delivered to, because its
+    /// circuit is open or its last delivery failed.
+    pub fn is_failing(&self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        self.circuit.as_deref() == Some("open")
+            || match (self.last_success, self.last_failure) {
+                (_, None) => false,
+                (None, Some(_)) => true,
+                (Some(success), Some(failure)) => failure > success,
+            }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Deliveries {
+    count: u64,
+    failures: u64,
+    last_success: Option<DateTime<Utc>>,
+    last_failure: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+/// Keeps track of the results of the deliveries to a target.
+#[derive(Debug, Default)]
+pub(crate) struct DeliveryStats {
+    deliveries: Mutex<Deliveries>,
+}
+
+impl DeliveryStats {
+    /// Records the result of a delivery.
+    pub(crate) fn record(&self, result: &Result<(), DeliveryError>, now: DateTime<Utc>) {
+        let mut deliveries = self
+            .deliveries
+            .lock()
+            .expect("delivery stats lock poisoned");
+
+        deliveries.count += 1;
+
+        match result {
+            Ok(()) => deliveries.last_success = Some(now),
+            Err(e) => {
+                deliveries.failures += 1;
+                deliveries.last_failure = Some(now);
+                deliveries.last_error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Takes over the results recorded by the same target of a previous gateway.
+    pub(crate) fn carry_over(&self, previous: &DeliveryStats) {
+        let previous = previous
+            .deliveries
+            .lock()
+            .expect("delivery stats lock poisoned")
+            .clone();
+
+        *self
+            .deliveries
+            .lock()
+            .expect("delivery stats lock poisoned") = previous;
+    }
+
+    /// Fills the delivery fields of the status of a target.
+    pub(crate) fn fill(&self, status: &mut TargetStatus) {
+        let deliveries = self
+            .deliveries
+            .lock()
+            .expect("delivery stats lock poisoned");
+
+        status.deliveries = deliveries.count;
+        status.failures = deliveries.failures;
+        status.last_success = deliveries.last_success;
+        status.last_failure = deliveries.last_failure;
+        status.last_error = deliveries.last_error.clone();
+    }
+}
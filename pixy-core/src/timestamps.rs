@@ -0,0 +1,306 @@
+//! Parsing and normalization of the timestamps sent by the sensors.
+//!
+//! The timestamp of a reading comes from the real-time clock of the board, which
+//! can drift or be reset when the battery runs out. Timestamps are parsed from the
+//! formats used by the Enviro firmware, normalized to RFC 3339, and compared to the
+//! time that the reading was received by the gateway. This only happens when the
+//! gateway is configured to handle timestamps; otherwise they are passed through
+//! as they were sent, only corrected by the clock offset of the device.
+
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, TimeDelta, Utc};
+use tracing::warn;
+
+use crate::config::{OnSkew, TimestampConfig};
+use crate::SensorMessage;
+
+/// The formats without a timezone that timestamps can be sent in. These are
+/// assumed to be in UTC, which is what the Enviro firmware uses.
+const NAIVE_FORMATS: [&str; 2] = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"];
+
+/// Parses a timestamp sent by a sensor. Supports RFC 3339, the formats used by
+/// the Enviro firmware, and Unix timestamps in seconds.
+pub fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+
+    for format in NAIVE_FORMATS {
+        if let Ok(timestamp) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(timestamp.and_utc());
+        }
+    }
+
+    value
+        .parse::<i64>()
+        .ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+}
+
+/// Formats a timestamp as RFC 3339, in UTC.
+pub(crate) fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// Corrects a timestamp by the clock offset of the device. Returns `None` if the
+/// corrected timestamp is out of range, which makes it an invalid timestamp.
+fn apply_offset(timestamp: DateTime<Utc>, clock_offset: i64) -> Option<DateTime<Utc>> {
+    timestamp.checked_add_signed(TimeDelta::try_seconds(clock_offset)?)
+}
+
+/// Sets the time a message was received, and normalizes its timestamp if timestamps
+/// are configured.
+///
+/// ## Arguments
+///
+/// * `message` - The message to normalize.
+/// * `config` - How to handle timestamps that are wrong, if set.
+/// * `clock_offset` - The number of seconds to add to the timestamp of the device.
+/// * `now` - The time the message was received.
+pub(crate) fn normalize(
+    message: &mut SensorMessage,
+    config: Option<&TimestampConfig>,
+    clock_offset: i64,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    message.received_at = Some(now);
+
+    let Some(config) = config else {
+        if clock_offset != 0 {
+            if let Some(timestamp) = parse_timestamp(&message.timestamp)
+                .and_then(|timestamp| apply_offset(timestamp, clock_offset))
+            {
+                message.timestamp = format_timestamp(&timestamp);
+            }
+        }
+
+        return Ok(());
+    };
+
+    let parsed = parse_timestamp(&message.timestamp).and_then(|t| apply_offset(t, clock_offset));
+
+    let skewed = match (parsed, config.max_skew) {
+        (None, _) => true,
+        (Some(timestamp), Some(max_skew)) => {
+            (timestamp - now).num_seconds().unsigned_abs() > max_skew
+        }
+        (Some(_), None) => false,
+    };
+
+    let timestamp = if skewed {
+        warn!(
+            uid = message.metadata.uid,
+            timestamp = message.timestamp,
+            received_at = %format_timestamp(&now),
+            "Received reading with an invalid or skewed timestamp"
+        );
+
+        match config.on_skew {
+            OnSkew::Keep => parsed,
+            OnSkew::Replace => Some(now),
+            OnSkew::Reject => {
+                return Err(format!(
+                    "Invalid or skewed timestamp: {}",
+                    message.timestamp
+                ))
+            }
+        }
+    } else {
+        parsed
+    };
+
+    let Some(timestamp) = timestamp else {
+        return Ok(());
+    };
+
+    if let Some(max_age) = config.max_age {
+        if (now - timestamp).num_seconds() > max_age as i64 {
+            return Err(format!(
+                "Reading is older than the maximum age of {} seconds",
+                max_age
+            ));
+        }
+    }
+
+    message.timestamp = format_timestamp(&timestamp);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_MESSAGE: &str = include_str!("../../example-configs/test-sensor.json");
+
+    fn message(timestamp: &str) -> SensorMessage {
+        let mut message: SensorMessage = serde_json::from_str(TEST_MESSAGE).unwrap();
+
+        message.timestamp = timestamp.to_string();
+
+        message
+    }
+
+    fn config(config: &str) -> Option<TimestampConfig> {
+        Some(serde_yaml::from_str(config).unwrap())
+    }
+
+    fn received() -> DateTime<Utc> {
+        parse_timestamp("2023-07-25T14:31:00Z").unwrap()
+    }
+
+    macro_rules! test_parse {
+        ($($a:ident: $b:expr,)*) => {
+        mod parse {
+            use super::*;
+            $(
+                #[test]
+                fn $a() {
+                    let parsed = parse_timestamp($b).unwrap();
+
+                    assert_eq!(format_timestamp(&parsed), "2023-07-25T14:30:13Z");
+                }
+            )*
+        }
+        };
+    }
+
+    test_parse!(
+        rfc3339: "2023-07-25T14:30:13Z",
+        rfc3339_offset: "2023-07-25T16:30:13+02:00",
+        enviro_space: "2023-07-25 14:30:13",
+        enviro_naive: "2023-07-25T14:30:13",
+        unix_seconds: "1690295413",
+    );
+
+    #[test]
+    fn test_invalid_timestamp_is_not_parsed() {
+        assert!(parse_timestamp("yesterday").is_none());
+    }
+
+    #[test]
+    fn test_timestamp_is_normalized() {
+        let mut message = message("2023-07-25 14:30:13");
+
+        normalize(&mut message, config("{}").as_ref(), 0, received()).unwrap();
+
+        assert_eq!(message.timestamp, "2023-07-25T14:30:13Z");
+        assert_eq!(message.received_at, Some(received()));
+    }
+
+    #[test]
+    fn test_invalid_timestamp_is_kept_by_default() {
+        let mut message = message("yesterday");
+
+        normalize(&mut message, config("{}").as_ref(), 0, received()).unwrap();
+
+        assert_eq!(message.timestamp, "yesterday");
+    }
+
+    #[test]
+    fn test_skewed_timestamp_is_replaced() {
+        let mut message = message("2000-01-01T00:00:00Z");
+
+        normalize(
+            &mut message,
+            config("{ maxSkew: 300, onSkew: replace }").as_ref(),
+            0,
+            received(),
+        )
+        .unwrap();
+
+        assert_eq!(message.timestamp, "2023-07-25T14:31:00Z");
+    }
+
+    #[test]
+    fn test_skewed_timestamp_is_rejected() {
+        let mut message = message("2000-01-01T00:00:00Z");
+
+        let result = normalize(
+            &mut message,
+            config("{ maxSkew: 300, onSkew: reject }").as_ref(),
+            0,
+            received(),
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_clock_offset_corrects_timestamp() {
+        let mut message = message("2023-07-25T13:30:13Z");
+
+        normalize(
+            &mut message,
+            config("{ maxSkew: 300, onSkew: reject }").as_ref(),
+            3600,
+            received(),
+        )
+        .unwrap();
+
+        assert_eq!(message.timestamp, "2023-07-25T14:30:13Z");
+    }
+
+    #[test]
+    fn test_old_readings_are_rejected() {
+        let mut message = message("2023-07-25T13:30:13Z");
+
+        let result = normalize(
+            &mut message,
+            config("{ maxAge: 3600 }").as_ref(),
+            0,
+            received(),
+        );
+
+        assert_eq!(
+            result,
+            Err(String::from(
+                "Reading is older than the maximum age of 3600 seconds"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_timestamp_is_passed_through_without_config() {
+        let mut passed = message("2023-07-25 14:30:13");
+
+        normalize(&mut passed, None, 0, received()).unwrap();
+
+        assert_eq!(passed.timestamp, "2023-07-25 14:30:13");
+        assert_eq!(passed.received_at, Some(received()));
+
+        let mut offset = message("2023-07-25 13:30:13");
+
+        normalize(&mut offset, None, 3600, received()).unwrap();
+
+        assert_eq!(offset.timestamp, "2023-07-25T14:30:13Z");
+    }
+
+    #[test]
+    fn test_out_of_range_offset_makes_timestamp_invalid() {
+        for clock_offset in [i64::MIN, i64::MAX, 400_000 * 365 * 86_400] {
+            let mut passed = message("2023-07-25 14:30:13");
+
+            normalize(&mut passed, None, clock_offset, received()).unwrap();
+
+            assert_eq!(passed.timestamp, "2023-07-25 14:30:13");
+
+            let mut rejected = message("2023-07-25 14:30:13");
+
+            let result = normalize(
+                &mut rejected,
+                config("{ onSkew: reject }").as_ref(),
+                clock_offset,
+                received(),
+            );
+
+            assert_eq!(
+                result,
+                Err(String::from(
+                    "Invalid or skewed timestamp: 2023-07-25 14:30:13"
+                ))
+            );
+        }
+    }
+}
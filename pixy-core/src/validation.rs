@@ -1,4 +1,5 @@
 use crate::config::ConfigFile;
+use crate::SensorGateway;
 
 use std::fs::File;
 use tracing::debug;
@@ -9,7 +10,7 @@ fn validate_config(config_value: &serde_json::Value) -> Result<(), String> {
     let schema_value: serde_json::Value =
         serde_json::from_str(GATEWAY_SCHEMA).map_err(|e| format!("Error parsing schema: {}", e))?;
 
-    let schema = jsonschema::JSONSchema::compile(&schema_value)
+    let schema = jsonschema::Validator::new(&schema_value)
         .map_err(|e| format!("Error compiling schema: {}", e))?;
 
     schema.validate(config_value).map_err(|e| {
@@ -47,6 +48,14 @@ pub fn parse_configs(file_name: &str) -> Result<ConfigFile, String> {
     Ok(config_file)
 }
 
+/// Checks a configuration file, including the settings that are only checked when
+/// the gateway is built, such as the TLS and proxy settings of the targets.
+pub fn validate(file_name: &str) -> Result<(), String> {
+    SensorGateway::try_from(parse_configs(file_name)?)?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,5 +94,47 @@ mod tests {
         simple: "../example-configs/echo-server.yaml",
         emit_to_self: "../example-configs/emit-to-pixy.yaml",
         many_webhooks: "../example-configs/webhook.yaml",
+        readings: "../example-configs/readings.yaml",
     );
+
+    #[test]
+    fn test_validate_builds_gateway() {
+        assert!(validate("../example-configs/echo-server.yaml").is_ok());
+    }
+
+    #[test]
+    fn test_invalid_client_settings_fail_validation() {
+        let path = std::env::temp_dir().join(format!("pixy-validate-{}.yaml", std::process::id()));
+
+        std::fs::write(
+            &path,
+            "targets:\n  - name: influx\n    webhook:\n      url: http://localhost:8086\n      tls:\n        caCerts: [/does/not/exist.pem]\n",
+        )
+        .unwrap();
+
+        let result = validate(&path.to_string_lossy());
+
+        let _ = std::fs::remove_file(&path);
+
+        assert!(result
+            .unwrap_err()
+            .starts_with("Invalid client settings for target influx"));
+    }
+
+    #[test]
+    fn test_tolerance_without_signed_timestamp_fails_validation() {
+        let config = serde_json::json!({
+            "targets": [],
+            "verification": { "canonical": "body", "tolerance": 60 }
+        });
+
+        assert!(validate_config(&config).is_err());
+
+        let config = serde_json::json!({
+            "targets": [],
+            "verification": { "canonical": "body" }
+        });
+
+        assert!(validate_config(&config).is_ok());
+    }
 }
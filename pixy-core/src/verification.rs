@@ -0,0 +1,294 @@
+//! Verification of the signatures of the payloads sent to the gateway.
+//!
+//! Upstream Pixy instances and custom firmware can sign the payloads they send with
+//! a shared secret, using the same format as [signing](crate::signing). The secret is
+//! either one of the configured `keys`, selected by the key id header, or the secret
+//! of the device that sent the reading, selected by its `uid`.
+//!
+//! Only the `timestampBody` format protects against replayed payloads. With `body`,
+//! the timestamp is not signed, so it is not checked at all and a captured payload
+//! can be sent again.
+
+use std::collections::HashMap;
+
+use minijinja::{value::Value, Environment};
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::config::{CanonicalFormat, DeviceConfig, VerificationConfig};
+use crate::signing;
+
+/// The part of a payload needed to find the secret of the device that sent it.
+#[derive(Deserialize)]
+struct Sender {
+    uid: String,
+}
+
+/// The largest difference between the signed timestamp and the current time, in
+/// seconds, unless configured otherwise.
+const DEFAULT_TOLERANCE: u64 = 300;
+
+#[derive(Debug)]
+pub(crate) struct SignatureVerifier {
+    config: VerificationConfig,
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+impl SignatureVerifier {
+    /// Creates a verifier. Fails if a `tolerance` is set when only the body is
+    /// signed, since it would not protect against replayed payloads.
+    pub(crate) fn new(config: &VerificationConfig) -> Result<Self, String> {
+        if config.canonical == CanonicalFormat::Body && config.tolerance.is_some() {
+            return Err(String::from(
+                "Verification cannot use a tolerance when only the body is signed",
+            ));
+        }
+
+        Ok(Self {
+            config: config.clone(),
+        })
+    }
+
+    /// Finds the secret that a payload should be signed with.
+    fn secret<'a>(
+        &'a self,
+        headers: &HeaderMap,
+        body: &[u8],
+        devices: &'a HashMap<String, DeviceConfig>,
+    ) -> Result<&'a str, String> {
+        if let Some(key_id) = header(headers, &self.config.key_id_header) {
+            return self
+                .config
+                .keys
+                .get(key_id)
+                .map(String::as_str)
+                .ok_or_else(|| format!("Unknown key id: {}", key_id));
+        }
+
+        let sender: Sender = serde_json::from_slice(body)
+            .map_err(|_| String::from("Cannot identify the device that sent the payload"))?;
+
+        devices
+            .get(&sender.uid)
+            .and_then(|device| device.secret.as_deref())
+            .ok_or_else(|| format!("No secret configured for device {}", sender.uid))
+    }
+
+    /// Verifies the signature of a payload.
+    ///
+    /// ## Arguments
+    ///
+    /// * `headers` - The headers of the request.
+    /// * `body` - The raw body of the request.
+    /// * `devices` - The settings of the devices, holding their secrets.
+    /// * `context` - The context that secrets are rendered with.
+    /// * `now` - The current Unix timestamp, in seconds.
+    pub(crate) fn verify(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+        devices: &HashMap<String, DeviceConfig>,
+        context: &Value,
+        now: i64,
+    ) -> Result<(), String> {
+        let Some(signature) = header(headers, &self.config.header) else {
+            return if self.config.required {
+                Err(String::from("Missing signature"))
+            } else {
+                Ok(())
+            };
+        };
+
+        let timestamp = match self.config.canonical {
+            CanonicalFormat::TimestampBody => {
+                let timestamp = header(headers, &self.config.timestamp_header)
+                    .ok_or_else(|| String::from("Missing signature timestamp"))?
+                    .trim()
+                    .parse::<i64>()
+                    .map_err(|_| String::from("Invalid signature timestamp"))?;
+
+                let tolerance = self.config.tolerance.unwrap_or(DEFAULT_TOLERANCE);
+
+                if now.abs_diff(timestamp) > tolerance {
+                    return Err(String::from("Signature timestamp is outside of tolerance"));
+                }
+
+                timestamp
+            }
+            CanonicalFormat::Body => 0,
+        };
+
+        // Which keys exist is not revealed to senders that cannot sign with them
+        let secret = self.secret(headers, body, devices).map_err(|e| {
+            debug!(reason = e, "No secret to verify the signature with");
+            String::from("Invalid signature")
+        })?;
+
+        let secret = Environment::new()
+            .render_str(secret, context)
+            .map_err(|e| e.to_string())?;
+
+        if signing::verify(
+            self.config.algorithm,
+            self.config.canonical,
+            secret.as_bytes(),
+            timestamp,
+            body,
+            signature,
+        ) {
+            Ok(())
+        } else {
+            Err(String::from("Invalid signature"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minijinja::context;
+
+    const TEST_MESSAGE: &str = include_str!("../../example-configs/test-sensor.json");
+    const NOW: i64 = 1690295413;
+
+    fn verifier(config: &str) -> SignatureVerifier {
+        SignatureVerifier::new(&serde_yaml::from_str(config).unwrap()).unwrap()
+    }
+
+    fn devices() -> HashMap<String, DeviceConfig> {
+        serde_yaml::from_str("{ e6614864d3898034: { secret: device-secret } }").unwrap()
+    }
+
+    fn signed_headers(secret: &str, timestamp: i64, key_id: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        let signature = signing::sign(
+            Default::default(),
+            Default::default(),
+            secret.as_bytes(),
+            timestamp,
+            TEST_MESSAGE.as_bytes(),
+        );
+
+        headers.insert("X-Pixy-Signature", signature.parse().unwrap());
+        headers.insert("X-Pixy-Timestamp", timestamp.to_string().parse().unwrap());
+
+        if let Some(key_id) = key_id {
+            headers.insert("X-Pixy-Key-Id", key_id.parse().unwrap());
+        }
+
+        headers
+    }
+
+    fn verify(verifier: &SignatureVerifier, headers: &HeaderMap) -> Result<(), String> {
+        verifier.verify(
+            headers,
+            TEST_MESSAGE.as_bytes(),
+            &devices(),
+            &context!(),
+            NOW,
+        )
+    }
+
+    #[test]
+    fn test_device_secret_is_used() {
+        let verifier = verifier("{}");
+
+        assert_eq!(
+            verify(&verifier, &signed_headers("device-secret", NOW, None)),
+            Ok(())
+        );
+        assert_eq!(
+            verify(&verifier, &signed_headers("wrong", NOW, None)),
+            Err(String::from("Invalid signature"))
+        );
+    }
+
+    #[test]
+    fn test_key_id_selects_key() {
+        let verifier = verifier("keys: { upstream: upstream-secret }");
+
+        assert_eq!(
+            verify(
+                &verifier,
+                &signed_headers("upstream-secret", NOW, Some("upstream"))
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            verify(
+                &verifier,
+                &signed_headers("upstream-secret", NOW, Some("unknown"))
+            ),
+            Err(String::from("Invalid signature"))
+        );
+    }
+
+    #[test]
+    fn test_tolerance_requires_signed_timestamp() {
+        let config = serde_yaml::from_str("{ canonical: body, tolerance: 60 }").unwrap();
+
+        assert!(SignatureVerifier::new(&config).is_err());
+    }
+
+    #[test]
+    fn test_body_signature_ignores_timestamp() {
+        let verifier = verifier("canonical: body");
+
+        let mut headers = HeaderMap::new();
+        let signature = signing::sign(
+            Default::default(),
+            CanonicalFormat::Body,
+            b"device-secret",
+            0,
+            TEST_MESSAGE.as_bytes(),
+        );
+
+        headers.insert("X-Pixy-Signature", signature.parse().unwrap());
+        headers.insert("X-Pixy-Timestamp", "0".parse().unwrap());
+
+        assert_eq!(verify(&verifier, &headers), Ok(()));
+    }
+
+    #[test]
+    fn test_replayed_payloads_are_rejected() {
+        let verifier = verifier("tolerance: 300");
+
+        assert!(verify(&verifier, &signed_headers("device-secret", NOW - 299, None)).is_ok());
+        assert_eq!(
+            verify(&verifier, &signed_headers("device-secret", NOW - 301, None)),
+            Err(String::from("Signature timestamp is outside of tolerance"))
+        );
+    }
+
+    #[test]
+    fn test_extreme_timestamps_are_rejected() {
+        let verifier = verifier("{}");
+
+        for timestamp in [i64::MIN, i64::MAX] {
+            let mut headers = signed_headers("device-secret", NOW, None);
+            headers.insert("X-Pixy-Timestamp", timestamp.to_string().parse().unwrap());
+
+            assert_eq!(
+                verify(&verifier, &headers),
+                Err(String::from("Signature timestamp is outside of tolerance"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_unsigned_payloads() {
+        assert_eq!(
+            verify(&verifier("{}"), &HeaderMap::new()),
+            Err(String::from("Missing signature"))
+        );
+        assert_eq!(
+            verify(&verifier("required: false"), &HeaderMap::new()),
+            Ok(())
+        );
+    }
+}
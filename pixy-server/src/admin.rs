@@ -0,0 +1,142 @@
+//! The admin API, for inspecting and controlling the targets of the gateway while
+//! it is running.
+//!
+//! The admin routes are only served when an admin token is configured, and every
+//! request to them must carry it as a bearer token.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use pixy_core::{signing::constant_time_eq, status::TargetStatus, Gateway};
+use tracing::{info, instrument, warn};
+
+/// Creates the router of the admin API, which is nested under `/admin`.
+///
+/// ## Arguments
+///
+/// * `token` - The bearer token that requests to the admin API must carry.
+pub(crate) fn create_admin_router(token: String) -> Router<Arc<dyn Gateway>> {
+    let token = Arc::new(token);
+
+    Router::new()
+        .route("/targets", get(list_targets))
+        .route("/targets/:name", get(get_target))
+        .route("/targets/:name/enable", post(enable_target))
+        .route("/targets/:name/disable", post(disable_target))
+        .route("/targets/:name/test", post(test_target))
+        .route("/reload", post(reload))
+        .layer(middleware::from_fn(move |request: Request, next: Next| {
+            let token = token.clone();
+            async move { authorize(&token, request, next).await }
+        }))
+}
+
+/// Rejects the requests that do not carry the admin token.
+async fn authorize(token: &str, request: Request, next: Next) -> Response {
+    let provided = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match provided {
+        Some(provided) if constant_time_eq(provided.as_bytes(), token.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => {
+            warn!(path = %request.uri().path(), "Rejected unauthorized admin request");
+            StatusCode::UNAUTHORIZED.into_response()
+        }
+    }
+}
+
+fn find_target(
+    gateway: &Arc<dyn Gateway>,
+    name: &str,
+) -> Result<TargetStatus, (StatusCode, String)> {
+    gateway
+        .targets()
+        .into_iter()
+        .find(|target| target.name == name)
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                format!("Target {} is not configured", name),
+            )
+        })
+}
+
+async fn list_targets(State(gateway): State<Arc<dyn Gateway>>) -> Json<Vec<TargetStatus>> {
+    Json(gateway.targets())
+}
+
+async fn get_target(
+    State(gateway): State<Arc<dyn Gateway>>,
+    Path(name): Path<String>,
+) -> Result<Json<TargetStatus>, (StatusCode, String)> {
+    find_target(&gateway, &name).map(Json)
+}
+
+async fn enable_target(
+    State(gateway): State<Arc<dyn Gateway>>,
+    Path(name): Path<String>,
+) -> Result<Json<TargetStatus>, (StatusCode, String)> {
+    set_enabled(&gateway, &name, true)
+}
+
+async fn disable_target(
+    State(gateway): State<Arc<dyn Gateway>>,
+    Path(name): Path<String>,
+) -> Result<Json<TargetStatus>, (StatusCode, String)> {
+    set_enabled(&gateway, &name, false)
+}
+
+fn set_enabled(
+    gateway: &Arc<dyn Gateway>,
+    name: &str,
+    enabled: bool,
+) -> Result<Json<TargetStatus>, (StatusCode, String)> {
+    find_target(gateway, name)?;
+
+    gateway
+        .set_target_enabled(name, enabled)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    find_target(gateway, name).map(Json)
+}
+
+#[instrument(skip(gateway))]
+async fn test_target(
+    State(gateway): State<Arc<dyn Gateway>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    find_target(&gateway, &name)?;
+
+    gateway
+        .test_target(&name)
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e))?;
+
+    info!("Test delivery succeeded");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[instrument(skip(gateway))]
+async fn reload(
+    State(gateway): State<Arc<dyn Gateway>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    gateway
+        .reload()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
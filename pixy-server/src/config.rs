@@ -1,23 +1,290 @@
 use config::{Config, ConfigError, Environment};
+use pixy_core::{config::ServerConfig, validation::parse_configs};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::listen::{parse_socket_mode, ListenAddress};
+
+/// The config file read when none is given.
+const DEFAULT_CONFIG_FILE: &str = "/pixy.yaml";
+
+/// What an admin token is replaced with when the configuration is shown.
+const REDACTED: &str = "<redacted>";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all(serialize = "camelCase"))]
 pub struct ServerConfiguration {
     pub port: u16,
+    /// The addresses to listen on, `host:port` or `unix:/path`. When empty, the
+    /// server listens on `port` on all IPv4 interfaces.
+    pub listen: Vec<String>,
+    /// The permissions of the Unix sockets, in octal.
+    pub socket_mode: Option<String>,
+    /// The PEM file of the certificate chain served over TLS on TCP addresses.
+    pub tls_cert: Option<String>,
+    /// The PEM file of the private key of the TLS certificate.
+    pub tls_key: Option<String>,
     pub log_level: String,
+    /// The format of the logs, `pretty`, `compact`, `json` or `logfmt`.
+    pub log_format: String,
+    /// The file the logs are written to instead of standard output.
+    pub log_file: Option<String>,
+    /// The size in megabytes after which the log file is rotated.
+    pub log_max_size: u64,
+    /// The number of rotated log files that are kept.
+    pub log_max_files: usize,
     pub config_file: String,
     pub enable_echo: bool,
+    pub enable_dashboard: bool,
+    /// Whether the readings are streamed on `/stream` and `/ws`.
+    pub enable_stream: bool,
+    /// The most clients that can stream the readings at once.
+    pub stream_max_clients: usize,
+    /// The largest reading accepted on `/data`, in kilobytes.
+    pub max_payload_size: usize,
+    /// The bearer token of the admin API, which is only served when it is set.
+    pub admin_token: Option<String>,
+    /// The URL of the OpenTelemetry collector that traces and metrics are sent to.
+    pub otlp_endpoint: Option<String>,
+    /// The protocol used to send telemetry to the collector, `grpc` or `http/protobuf`.
+    pub otlp_protocol: Option<String>,
+    /// The fewest enabled targets for the gateway to be ready.
+    pub ready_min_targets: usize,
+    /// The most failing targets for the gateway to be ready. When unset, it is
+    /// unready only if every enabled target is failing.
+    pub ready_max_failing: Option<usize>,
+    /// The most readings waiting in the backlog of a target for the gateway to be
+    /// ready.
+    pub ready_max_backlog: Option<usize>,
+}
+
+/// The settings given on the command line, which take precedence over the
+/// environment and the config file.
+#[derive(Debug, Default)]
+pub struct ServerOverrides {
+    pub config_file: Option<String>,
+    /// The config file read when none is given on the command line or in
+    /// `PIXY_CONFIG_FILE`, instead of `/pixy.yaml`.
+    pub default_config_file: Option<String>,
+    pub settings: ServerConfig,
 }
 
 impl ServerConfiguration {
-    pub fn build() -> Result<Self, ConfigError> {
+    /// Resolves the configuration of the server. Each setting is taken from the
+    /// first of these that sets it:
+    ///
+    /// 1. The command line, through the overrides
+    /// 2. The `PIXY_*` environment variables
+    /// 3. The `server` section of the config file
+    /// 4. The defaults
+    pub fn load(overrides: &ServerOverrides) -> Result<Self, String> {
+        let config_file = overrides
+            .config_file
+            .clone()
+            .or_else(|| std::env::var("PIXY_CONFIG_FILE").ok())
+            .or_else(|| overrides.default_config_file.clone())
+            .unwrap_or_else(|| String::from(DEFAULT_CONFIG_FILE));
+
+        let file = parse_configs(&config_file)?.server.unwrap_or_default();
+
+        Self::resolve(
+            &config_file,
+            &file,
+            Environment::with_prefix("PIXY"),
+            &overrides.settings,
+        )
+        .map_err(|e| format!("Error reading the server configuration: {}", e))
+    }
+
+    pub(crate) fn resolve(
+        config_file: &str,
+        file: &ServerConfig,
+        env: Environment,
+        overrides: &ServerConfig,
+    ) -> Result<Self, ConfigError> {
         Config::builder()
-            .add_source(Environment::with_prefix("PIXY"))
             .set_default("port", 9147)?
+            .set_default("listen", Vec::<String>::new())?
             .set_default("log_level", "info")?
-            .set_default("config_file", "/pixy.yaml")?
+            .set_default("log_format", "compact")?
+            .set_default("log_max_size", 10)?
+            .set_default("log_max_files", 5)?
             .set_default("enable_echo", false)?
+            .set_default("enable_dashboard", false)?
+            .set_default("enable_stream", false)?
+            .set_default("stream_max_clients", 16)?
+            .set_default("max_payload_size", 2048)?
+            .set_default("ready_min_targets", 1)?
+            .add_source(settings_source(file)?)
+            .add_source(
+                env.try_parsing(true)
+                    .list_separator(",")
+                    .with_list_parse_key("listen"),
+            )
+            .add_source(settings_source(overrides)?)
+            .set_override("config_file", config_file)?
             .build()?
             .try_deserialize()
     }
+
+    /// The configuration with its secrets redacted, to be shown to users.
+    pub fn redacted(&self) -> Self {
+        Self {
+            admin_token: self.admin_token.as_ref().map(|_| String::from(REDACTED)),
+            ..self.clone()
+        }
+    }
+
+    /// The addresses the server listens on, unless it was given sockets by
+    /// systemd.
+    pub fn listen_addresses(&self) -> Result<Vec<ListenAddress>, String> {
+        if self.listen.is_empty() {
+            return Ok(vec![ListenAddress::Tcp(format!("0.0.0.0:{}", self.port))]);
+        }
+
+        self.listen.iter().map(|address| address.parse()).collect()
+    }
+
+    /// The permissions of the Unix sockets, if set.
+    pub fn socket_mode(&self) -> Result<Option<u32>, String> {
+        self.socket_mode
+            .as_deref()
+            .map(parse_socket_mode)
+            .transpose()
+    }
+
+    /// The TLS certificate and key files, if both are set.
+    pub fn tls_files(&self) -> Result<Option<(&str, &str)>, String> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Ok(Some((cert, key))),
+            (None, None) => Ok(None),
+            _ => Err(String::from(
+                "Both the TLS certificate and its key must be set",
+            )),
+        }
+    }
+}
+
+/// The settings that are set, as a source of the configuration.
+fn settings_source(settings: &ServerConfig) -> Result<Config, ConfigError> {
+    Config::builder()
+        .set_override_option("port", settings.port)?
+        .set_override_option("listen", settings.listen.clone())?
+        .set_override_option("socket_mode", settings.socket_mode.clone())?
+        .set_override_option("tls_cert", settings.tls_cert.clone())?
+        .set_override_option("tls_key", settings.tls_key.clone())?
+        .set_override_option("enable_echo", settings.enable_echo)?
+        .set_override_option("enable_dashboard", settings.enable_dashboard)?
+        .set_override_option("enable_stream", settings.enable_stream)?
+        .set_override_option("stream_max_clients", settings.stream_max_clients)?
+        .set_override_option("max_payload_size", settings.max_payload_size)?
+        .set_override_option("admin_token", settings.admin_token.clone())?
+        .set_override_option("log_level", settings.log_level.clone())?
+        .set_override_option("log_format", settings.log_format.clone())?
+        .set_override_option("log_file", settings.log_file.clone())?
+        .set_override_option("log_max_size", settings.log_max_size)?
+        .set_override_option("log_max_files", settings.log_max_files)?
+        .set_override_option("otlp_endpoint", settings.otlp_endpoint.clone())?
+        .set_override_option("otlp_protocol", settings.otlp_protocol.clone())?
+        .set_override_option("ready_min_targets", settings.ready_min_targets)?
+        .set_override_option("ready_max_failing", settings.ready_max_failing)?
+        .set_override_option("ready_max_backlog", settings.ready_max_backlog)?
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Environment {
+        Environment::with_prefix("PIXY").source(Some(
+            vars.iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = ServerConfiguration::resolve(
+            "pixy.yaml",
+            &ServerConfig::default(),
+            env(&[]),
+            &ServerConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(config.port, 9147);
+        assert_eq!(config.log_format, "compact");
+        assert!(config.listen.is_empty());
+        assert!(!config.enable_echo);
+        assert!(!config.enable_stream);
+        assert_eq!(config.stream_max_clients, 16);
+        assert_eq!(config.max_payload_size, 2048);
+        assert_eq!(config.admin_token, None);
+        assert_eq!(config.ready_min_targets, 1);
+        assert_eq!(config.ready_max_failing, None);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let file = ServerConfig {
+            port: Some(8000),
+            enable_echo: Some(true),
+            log_format: Some(String::from("json")),
+            admin_token: Some(String::from("from-file")),
+            ..Default::default()
+        };
+
+        let overrides = ServerConfig {
+            port: Some(9000),
+            ..Default::default()
+        };
+
+        let config = ServerConfiguration::resolve(
+            "pixy.yaml",
+            &file,
+            env(&[
+                ("PIXY_PORT", "8500"),
+                ("PIXY_LOG_FORMAT", "logfmt"),
+                ("PIXY_LISTEN", "127.0.0.1:9147,unix:/run/pixy.sock"),
+                ("PIXY_CONFIG_FILE", "ignored.yaml"),
+            ]),
+            &overrides,
+        )
+        .unwrap();
+
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.log_format, "logfmt");
+        assert!(config.enable_echo);
+        assert_eq!(config.listen, ["127.0.0.1:9147", "unix:/run/pixy.sock"]);
+        assert_eq!(config.config_file, "pixy.yaml");
+        assert_eq!(config.redacted().admin_token.as_deref(), Some(REDACTED));
+    }
+
+    #[test]
+    fn test_tls_requires_certificate_and_key() {
+        let resolve = |settings: &ServerConfig| {
+            ServerConfiguration::resolve("pixy.yaml", settings, env(&[]), &ServerConfig::default())
+                .unwrap()
+        };
+
+        let config = resolve(&ServerConfig::default());
+
+        assert_eq!(config.tls_files(), Ok(None));
+
+        let config = resolve(&ServerConfig {
+            tls_cert: Some(String::from("cert.pem")),
+            tls_key: Some(String::from("key.pem")),
+            ..Default::default()
+        });
+
+        assert_eq!(config.tls_files(), Ok(Some(("cert.pem", "key.pem"))));
+
+        let config = resolve(&ServerConfig {
+            tls_cert: Some(String::from("cert.pem")),
+            ..Default::default()
+        });
+
+        assert!(config.tls_files().is_err());
+    }
 }
@@ -0,0 +1,64 @@
+//! A status page showing the boards, the targets and the recent log events of the
+//! gateway.
+//!
+//! The page is a single HTML file without any dependencies, which polls the status
+//! route to refresh itself.
+
+use std::sync::Arc;
+
+use axum::{extract::State, response::Html, Json};
+use chrono::{DateTime, Utc};
+use pixy_core::{status::TargetStatus, Gateway, SensorMessage};
+use serde::Serialize;
+
+use crate::events::{recent_events, LogEvent};
+
+const DASHBOARD_PAGE: &str = include_str!("dashboard.html");
+
+/// The state of a target shown on the dashboard, without its settings. The last
+/// error is left out as well, since it can contain the URL or the credentials of
+/// the target.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TargetSummary {
+    name: String,
+    enabled: bool,
+    circuit: Option<String>,
+    deliveries: u64,
+    failures: u64,
+    last_success: Option<DateTime<Utc>>,
+    last_failure: Option<DateTime<Utc>>,
+}
+
+impl From<TargetStatus> for TargetSummary {
+    fn from(status: TargetStatus) -> Self {
+        Self {
+            name: status.name,
+            enabled: status.enabled,
+            circuit: status.circuit,
+            deliveries: status.deliveries,
+            failures: status.failures,
+            last_success: status.last_success,
+            last_failure: status.last_failure,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct DashboardStatus {
+    devices: Vec<SensorMessage>,
+    targets: Vec<TargetSummary>,
+    events: Vec<LogEvent>,
+}
+
+pub(crate) async fn page() -> Html<&'static str> {
+    Html(DASHBOARD_PAGE)
+}
+
+pub(crate) async fn status(State(gateway): State<Arc<dyn Gateway>>) -> Json<DashboardStatus> {
+    Json(DashboardStatus {
+        devices: gateway.devices(),
+        targets: gateway.targets().into_iter().map(Into::into).collect(),
+        events: recent_events().events(),
+    })
+}
@@ -0,0 +1,144 @@
+//! Keeps the most recent log events in memory, so that they can be shown on the
+//! dashboard.
+//!
+//! The events are collected by a [`tracing_subscriber`] layer, which has to be
+//! added to the subscriber of the application with [`layer`].
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{field::Field, Event, Level, Subscriber};
+use tracing_subscriber::{field::Visit, layer::Context, Layer};
+
+/// The number of log events that are kept.
+const MAX_EVENTS: usize = 100;
+
+/// A log event, formatted for display.
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEvent {
+    pub time: DateTime<Utc>,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+/// The most recent log events, oldest first.
+#[derive(Debug, Default)]
+pub struct RecentEvents {
+    events: Mutex<VecDeque<LogEvent>>,
+}
+
+impl RecentEvents {
+    fn push(&self, event: LogEvent) {
+        let mut events = self.events.lock().expect("events lock poisoned");
+
+        if events.len() >= MAX_EVENTS {
+            events.pop_front();
+        }
+
+        events.push_back(event);
+    }
+
+    /// Returns the events that are kept, oldest first.
+    pub fn events(&self) -> Vec<LogEvent> {
+        self.events
+            .lock()
+            .expect("events lock poisoned")
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+/// The events collected by the layer of the application.
+pub fn recent_events() -> &'static RecentEvents {
+    static EVENTS: OnceLock<RecentEvents> = OnceLock::new();
+
+    EVENTS.get_or_init(RecentEvents::default)
+}
+
+/// Formats the fields of an event as its message, followed by its other fields.
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+}
+
+/// Collects the events at the info level and above into the recent events.
+pub struct EventsLayer {
+    events: &'static RecentEvents,
+}
+
+/// Creates the layer collecting the recent events of the application.
+pub fn layer() -> EventsLayer {
+    EventsLayer {
+        events: recent_events(),
+    }
+}
+
+impl<S: Subscriber> Layer<S> for EventsLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+
+        if *metadata.level() > Level::INFO {
+            return;
+        }
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        self.events.push(LogEvent {
+            time: Utc::now(),
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message: visitor.message + &visitor.fields,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_layer_keeps_recent_events() {
+        let events: &'static RecentEvents = Box::leak(Box::default());
+        let subscriber = tracing_subscriber::registry().with(EventsLayer { events });
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("Not kept");
+
+            for i in 0..=MAX_EVENTS {
+                tracing::info!(handler = "influx", "Delivered reading {}", i);
+            }
+        });
+
+        let events = events.events();
+
+        assert_eq!(events.len(), MAX_EVENTS);
+        assert_eq!(events[0].message, "Delivered reading 1 handler=influx");
+        assert_eq!(events[0].level, "INFO");
+    }
+}
@@ -0,0 +1,338 @@
+//! The addresses the server listens on, which can be TCP addresses, IPv4 or IPv6,
+//! and Unix domain sockets.
+//!
+//! When Pixy is started by systemd with socket activation, the sockets passed in
+//! `LISTEN_FDS` are used instead of the configured addresses.
+
+use std::fmt;
+use std::fs;
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::PathBuf;
+
+use axum::Router;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio_rustls::TlsAcceptor;
+use tracing::debug;
+
+use crate::tls;
+
+/// The first file descriptor passed by systemd, after standard input, output and
+/// error.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// An address to listen on, either `host:port` or `unix:/path/to/socket`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl std::str::FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = value.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(String::from("Missing path of the Unix socket"));
+            }
+
+            return Ok(ListenAddress::Unix(PathBuf::from(path)));
+        }
+
+        // IPv6 addresses are written in brackets, i.e. `[::1]:9147`
+        match value.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(ListenAddress::Tcp(value.to_string()))
+            }
+            _ => Err(format!(
+                "Invalid listen address {}, expected host:port or unix:/path",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => write!(f, "{}", address),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Parses the permissions of Unix sockets, written in octal, i.e. `660`.
+pub fn parse_socket_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("Invalid socket mode {}, expected octal such as 660", value))
+}
+
+/// A socket the server accepts connections on.
+#[derive(Debug)]
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Binds to an address. A Unix socket left over by a previous run is
+    /// replaced, and gets the given permissions. Any other file at the path is
+    /// left alone, and binding fails.
+    pub(crate) async fn bind(address: &ListenAddress, mode: Option<u32>) -> Result<Self, String> {
+        match address {
+            ListenAddress::Tcp(address) => TcpListener::bind(address)
+                .await
+                .map(Listener::Tcp)
+                .map_err(|e| format!("Error listening on {}: {}", address, e)),
+            ListenAddress::Unix(path) => {
+                let leftover = fs::symlink_metadata(path)
+                    .map(|metadata| metadata.file_type().is_socket())
+                    .unwrap_or(false);
+
+                if leftover {
+                    fs::remove_file(path)
+                        .map_err(|e| format!("Error removing {}: {}", path.display(), e))?;
+                }
+
+                let listener = UnixListener::bind(path)
+                    .map_err(|e| format!("Error listening on {}: {}", path.display(), e))?;
+
+                if let Some(mode) = mode {
+                    fs::set_permissions(path, fs::Permissions::from_mode(mode)).map_err(|e| {
+                        format!("Error setting permissions of {}: {}", path.display(), e)
+                    })?;
+                }
+
+                Ok(Listener::Unix(listener))
+            }
+        }
+    }
+
+    /// Takes the sockets passed by systemd with socket activation, if any.
+    pub(crate) fn from_systemd() -> Result<Vec<Self>, String> {
+        let Some(count) = systemd_fds(
+            std::env::var("LISTEN_PID").ok().as_deref(),
+            std::env::var("LISTEN_FDS").ok().as_deref(),
+        ) else {
+            return Ok(Vec::new());
+        };
+
+        (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+            .map(|fd| {
+                // Safety: systemd passes these descriptors to this process, and
+                // nothing else in the process takes ownership of them
+                unsafe { Self::from_raw_fd(fd) }
+                    .map_err(|e| format!("Error using socket {} from systemd: {}", fd, e))
+            })
+            .collect()
+    }
+
+    unsafe fn from_raw_fd(fd: RawFd) -> std::io::Result<Self> {
+        let unix = std::os::unix::net::UnixListener::from_raw_fd(fd);
+
+        // The address of a socket that is not a Unix socket cannot be read as one
+        if unix.local_addr().is_ok() {
+            unix.set_nonblocking(true)?;
+            return UnixListener::from_std(unix).map(Listener::Unix);
+        }
+
+        let tcp = std::net::TcpListener::from_raw_fd(unix.into_raw_fd());
+        tcp.set_nonblocking(true)?;
+        TcpListener::from_std(tcp).map(Listener::Tcp)
+    }
+
+    /// A description of the address of the socket, for the logs.
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Listener::Tcp(listener) => listener
+                .local_addr()
+                .map(|address| address.to_string())
+                .unwrap_or_else(|_| String::from("unknown address")),
+            Listener::Unix(listener) => listener
+                .local_addr()
+                .ok()
+                .and_then(|address| Some(format!("unix:{}", address.as_pathname()?.display())))
+                .unwrap_or_else(|| String::from("unix socket")),
+        }
+    }
+
+    /// Serves the application on the socket until an error stops it. TCP sockets
+    /// are served over TLS when an acceptor is given.
+    pub(crate) async fn serve(self, app: Router, tls: Option<TlsAcceptor>) -> std::io::Result<()> {
+        match (self, tls) {
+            (Listener::Tcp(listener), None) => axum::serve(listener, app.into_make_service()).await,
+            (Listener::Tcp(listener), Some(tls)) => loop {
+                let (stream, _) = listener.accept().await?;
+                let (tls, app) = (tls.clone(), app.clone());
+
+                tokio::spawn(async move {
+                    match tokio::time::timeout(tls::HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                        Ok(Ok(stream)) => serve_connection(stream, app).await,
+                        Ok(Err(e)) => debug!(error = %e, "Error in the TLS handshake"),
+                        Err(_) => debug!("TLS handshake timed out"),
+                    }
+                });
+            },
+            (Listener::Unix(listener), _) => loop {
+                let (stream, _) = listener.accept().await?;
+
+                tokio::spawn(serve_connection(stream, app.clone()));
+            },
+        }
+    }
+}
+
+/// Serves the application on a connection accepted by hand, with HTTP/1.1 or
+/// HTTP/2 and WebSocket upgrades.
+async fn serve_connection<S>(stream: S, app: Router)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    if let Err(e) = Builder::new(TokioExecutor::new())
+        .serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(app))
+        .await
+    {
+        debug!(error = %e, "Error serving connection");
+    }
+}
+
+/// The number of sockets passed by systemd, if they were passed to this process.
+fn systemd_fds(pid: Option<&str>, fds: Option<&str>) -> Option<RawFd> {
+    if pid?.parse::<u32>().ok()? != std::process::id() {
+        return None;
+    }
+
+    fds?.parse().ok().filter(|count| *count > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listen_address() {
+        assert_eq!(
+            "127.0.0.1:9147".parse(),
+            Ok(ListenAddress::Tcp(String::from("127.0.0.1:9147")))
+        );
+        assert_eq!(
+            "[::]:9147".parse(),
+            Ok(ListenAddress::Tcp(String::from("[::]:9147")))
+        );
+        assert_eq!(
+            "unix:/run/pixy/pixy.sock".parse(),
+            Ok(ListenAddress::Unix(PathBuf::from("/run/pixy/pixy.sock")))
+        );
+        assert!("9147".parse::<ListenAddress>().is_err());
+        assert!("localhost".parse::<ListenAddress>().is_err());
+        assert!("unix:".parse::<ListenAddress>().is_err());
+    }
+
+    #[test]
+    fn test_parse_socket_mode() {
+        assert_eq!(parse_socket_mode("660"), Ok(0o660));
+        assert!(parse_socket_mode("999").is_err());
+        assert!(parse_socket_mode("7777").is_err());
+    }
+
+    #[test]
+    fn test_systemd_fds_only_for_this_process() {
+        let pid = std::process::id().to_string();
+
+        assert_eq!(systemd_fds(Some(&pid), Some("2")), Some(2));
+        assert_eq!(systemd_fds(Some("1"), Some("2")), None);
+        assert_eq!(systemd_fds(Some(&pid), Some("0")), None);
+        assert_eq!(systemd_fds(None, Some("2")), None);
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_gets_permissions() {
+        let path = std::env::temp_dir().join(format!("pixy-{}.sock", std::process::id()));
+        let address = ListenAddress::Unix(path.clone());
+
+        let listener = Listener::bind(&address, Some(0o660)).await.unwrap();
+
+        assert_eq!(listener.describe(), address.to_string());
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o660
+        );
+
+        // A socket left over by a previous run is replaced
+        drop(listener);
+        Listener::bind(&address, None).await.unwrap();
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tcp_is_served_over_tls() {
+        use std::sync::Arc;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let cert = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/localhost.pem");
+        let key = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/localhost-key.pem");
+
+        let listener = Listener::bind(&ListenAddress::Tcp(String::from("127.0.0.1:0")), None)
+            .await
+            .unwrap();
+        let address = listener.describe();
+
+        let app = Router::new().route("/healthz", axum::routing::get(|| async { "ok" }));
+        tokio::spawn(listener.serve(app, Some(tls::acceptor(cert, key).unwrap())));
+
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in
+            rustls_pemfile::certs(&mut std::io::BufReader::new(fs::File::open(cert).unwrap()))
+        {
+            roots.add(cert.unwrap()).unwrap();
+        }
+
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+        let stream = tokio::net::TcpStream::connect(&address).await.unwrap();
+        let mut stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+            .connect("localhost".try_into().unwrap(), stream)
+            .await
+            .unwrap();
+
+        stream
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("ok"));
+    }
+
+    #[tokio::test]
+    async fn test_other_files_are_not_replaced() {
+        let path = std::env::temp_dir().join(format!("pixy-{}.yaml", std::process::id()));
+        let address = ListenAddress::Unix(path.clone());
+
+        fs::write(&path, "targets: []").unwrap();
+
+        assert!(Listener::bind(&address, None).await.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "targets: []");
+
+        fs::remove_file(path).unwrap();
+    }
+}
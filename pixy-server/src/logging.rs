@@ -0,0 +1,542 @@
+//! Sets up the log output of the application, in a human-readable or a structured
+//! format, on standard output or in a log file rotated by size.
+//!
+//! The structured formats, `json` and `logfmt`, write one line per event with the
+//! fields of the event and of all the spans it is in, so that every event of a
+//! delivery carries the target, the board and the request it belongs to.
+
+use std::fmt::{self, Write as _};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::Value;
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    span, Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    fmt::{format::Writer, writer::BoxMakeWriter, FmtContext, FormatEvent, FormatFields},
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    Layer, Registry,
+};
+
+use crate::{config::ServerConfiguration, events, telemetry::Telemetry};
+
+/// The format of the log output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Multi-line, human-readable events.
+    Pretty,
+    /// Single-line, human-readable events.
+    Compact,
+    /// One JSON object per line.
+    Json,
+    /// One line of `key=value` pairs per event.
+    Logfmt,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "pretty" => Ok(LogFormat::Pretty),
+            "compact" => Ok(LogFormat::Compact),
+            "json" => Ok(LogFormat::Json),
+            "logfmt" => Ok(LogFormat::Logfmt),
+            _ => Err(format!(
+                "Unsupported log format {}, expected pretty, compact, json or logfmt",
+                value
+            )),
+        }
+    }
+}
+
+/// Where and how the logs are written.
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    pub format: LogFormat,
+    /// The file the logs are written to instead of standard output.
+    pub file: Option<String>,
+    /// The size in bytes after which the log file is rotated.
+    pub max_size: u64,
+    /// The number of rotated log files that are kept.
+    pub max_files: usize,
+    /// Whether the human-readable formats are coloured on standard output.
+    pub ansi: bool,
+}
+
+/// Sets up the logs and telemetry of the server from its resolved configuration,
+/// and returns the telemetry, which must be shut down before exiting.
+pub fn setup(config: &ServerConfiguration, ansi: bool) -> Result<Option<Telemetry>, String> {
+    let level = config
+        .log_level
+        .parse::<Level>()
+        .map_err(|_| format!("Invalid log level: {}", config.log_level))?;
+
+    let telemetry = Telemetry::init(
+        config.otlp_endpoint.as_deref(),
+        config.otlp_protocol.as_deref(),
+    )?;
+
+    let options = LogOptions {
+        format: config.log_format.parse()?,
+        file: config.log_file.clone(),
+        max_size: config.log_max_size * 1024 * 1024,
+        max_files: config.log_max_files,
+        ansi,
+    };
+
+    init(&options, LevelFilter::from_level(level), telemetry.as_ref())?;
+
+    Ok(telemetry)
+}
+
+/// Sets up the global subscriber of the application with the log output, the
+/// recent events of the dashboard and, if configured, the export of spans to an
+/// OpenTelemetry collector.
+pub fn init(
+    options: &LogOptions,
+    level: LevelFilter,
+    telemetry: Option<&Telemetry>,
+) -> Result<(), String> {
+    let (writer, ansi) = match &options.file {
+        Some(path) => (
+            BoxMakeWriter::new(Mutex::new(RotatingFile::open(
+                path,
+                options.max_size,
+                options.max_files,
+            )?)),
+            false,
+        ),
+        None => (BoxMakeWriter::new(io::stdout), options.ansi),
+    };
+
+    let output: Box<dyn Layer<Registry> + Send + Sync> = match options.format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer()
+            .pretty()
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Compact => tracing_subscriber::fmt::layer()
+            .compact()
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Json | LogFormat::Logfmt => tracing_subscriber::fmt::layer()
+            .event_format(StructuredFormat(options.format))
+            .with_writer(writer)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(output)
+        .with(SpanFieldsLayer)
+        .with(events::layer())
+        .with(telemetry.map(Telemetry::layer))
+        .with(level)
+        .try_init()
+        .map_err(|e| format!("Error setting up logging: {}", e))
+}
+
+/// The fields of an event or a span, in the order they were recorded.
+#[derive(Debug, Clone, Default)]
+struct Fields(Vec<(String, Value)>);
+
+impl Fields {
+    /// Sets a field, replacing its previous value if it was already set.
+    fn insert(&mut self, key: &str, value: Value) {
+        match self.0.iter_mut().find(|(existing, _)| existing == key) {
+            Some((_, existing)) => *existing = value,
+            None => self.0.push((key.to_string(), value)),
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Value> {
+        let index = self.0.iter().position(|(existing, _)| existing == key)?;
+        Some(self.0.remove(index).1)
+    }
+
+    fn extend(&mut self, other: &Fields) {
+        for (key, value) in &other.0 {
+            self.insert(key, value.clone());
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let fields: Vec<String> = self
+            .0
+            .iter()
+            .map(|(key, value)| format!("{}:{}", Value::from(key.as_str()), value))
+            .collect();
+
+        format!("{{{}}}", fields.join(","))
+    }
+
+    /// Formats the fields as `key=value` pairs, quoting the values that need it.
+    fn to_logfmt(&self) -> String {
+        let mut line = String::new();
+
+        for (key, value) in &self.0 {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+
+            let value = match value {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+
+            if value.is_empty() || value.contains([' ', '"', '=', '\\', '\n']) {
+                let _ = write!(line, "{}={:?}", key, value);
+            } else {
+                let _ = write!(line, "{}={}", key, value);
+            }
+        }
+
+        line
+    }
+}
+
+/// The fields of a span, kept so that they can be added to the structured events
+/// in the span.
+struct SpanFields(Fields);
+
+/// Collects the fields of the spans into their [`SpanFields`].
+struct SpanFieldsLayer;
+
+impl<S> Layer<S> for SpanFieldsLayer
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut visitor = FieldsVisitor::default();
+        attrs.record(&mut visitor);
+        span.extensions_mut().insert(SpanFields(visitor.0));
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut extensions = span.extensions_mut();
+
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            let mut visitor = FieldsVisitor(std::mem::take(fields));
+            values.record(&mut visitor);
+            *fields = visitor.0;
+        }
+    }
+}
+
+/// Records fields as JSON values, keeping numbers and booleans as such.
+#[derive(Default)]
+struct FieldsVisitor(Fields);
+
+impl Visit for FieldsVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name(), format!("{:?}", value).into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name(), value.into());
+    }
+}
+
+/// Formats events as a single line of JSON or logfmt, with the fields of the
+/// spans they are in.
+struct StructuredFormat(LogFormat);
+
+impl<S, N> FormatEvent<S, N> for StructuredFormat
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut fields = Fields::default();
+
+        fields.insert(
+            "time",
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Millis, true)
+                .into(),
+        );
+        fields.insert("level", metadata.level().as_str().into());
+        fields.insert("target", metadata.target().into());
+
+        // The fields of the inner spans take precedence over the outer ones, and
+        // the fields of the event over all of them
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                if let Some(SpanFields(span_fields)) = span.extensions().get::<SpanFields>() {
+                    fields.extend(span_fields);
+                }
+            }
+        }
+
+        let mut visitor = FieldsVisitor::default();
+        event.record(&mut visitor);
+        let mut event_fields = visitor.0;
+
+        if let Some(message) = event_fields.remove("message") {
+            fields.insert("message", message);
+        }
+
+        fields.extend(&event_fields);
+
+        match self.0 {
+            LogFormat::Logfmt => writeln!(writer, "{}", fields.to_logfmt()),
+            _ => writeln!(writer, "{}", fields.to_json()),
+        }
+    }
+}
+
+/// A log file that is rotated once it reaches a maximum size. The rotated files
+/// get a numbered suffix, `.1` being the most recent, and the oldest ones are
+/// removed.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    pub fn open(path: impl AsRef<Path>, max_size: u64, max_files: usize) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let file = Self::create(&path)?;
+        let size = file
+            .metadata()
+            .map_err(|e| format!("Error reading log file {}: {}", path.display(), e))?
+            .len();
+
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn create(path: &Path) -> Result<File, String> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Error opening log file {}: {}", path.display(), e))
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated(index);
+
+                if from.exists() {
+                    fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        self.file = Self::create(&self.path).map_err(io::Error::other)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::{info, info_span};
+    use tracing_subscriber::fmt::MakeWriter;
+
+    /// Collects the log lines written by a subscriber.
+    #[derive(Clone, Default)]
+    struct Lines(std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Lines {
+        type Writer = Lines;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn log_delivery(format: LogFormat) -> String {
+        let lines = Lines::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .event_format(StructuredFormat(format))
+                    .with_writer(lines.clone()),
+            )
+            .with(SpanFieldsLayer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _request = info_span!("request", request_id = "abc").entered();
+            let _reading = info_span!("reading", uid = "pixy-test").entered();
+
+            info!(handler = "influx", latency_ms = 12, "Delivered reading");
+        });
+
+        let output = lines.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!("logfmt".parse(), Ok(LogFormat::Logfmt));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn test_json_includes_span_fields() {
+        let line: Value = serde_json::from_str(&log_delivery(LogFormat::Json)).unwrap();
+
+        assert_eq!(line["message"], "Delivered reading");
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["request_id"], "abc");
+        assert_eq!(line["uid"], "pixy-test");
+        assert_eq!(line["handler"], "influx");
+        assert_eq!(line["latency_ms"], 12);
+    }
+
+    #[test]
+    fn test_logfmt_includes_span_fields() {
+        let line = log_delivery(LogFormat::Logfmt);
+
+        assert!(line.contains(" message=\"Delivered reading\" "));
+        assert!(line.contains(" request_id=abc uid=pixy-test "));
+        assert!(line.ends_with(" handler=influx latency_ms=12\n"));
+    }
+
+    #[test]
+    fn test_file_is_rotated_by_size() {
+        let dir = std::env::temp_dir().join(format!("pixy-logs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("pixy.log");
+
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("pixy.log.1")).unwrap(),
+            "third\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("pixy.log.2")).unwrap(),
+            "second\n"
+        );
+        assert!(!dir.join("pixy.log.3").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_setup_rejects_invalid_settings() {
+        let configuration = |settings: pixy_core::config::ServerConfig| {
+            ServerConfiguration::resolve(
+                "pixy.yaml",
+                &Default::default(),
+                config::Environment::with_prefix("PIXY").source(Some(Default::default())),
+                &settings,
+            )
+            .unwrap()
+        };
+
+        let level = configuration(pixy_core::config::ServerConfig {
+            log_level: Some(String::from("loud")),
+            ..Default::default()
+        });
+
+        assert_eq!(setup(&level, false).unwrap_err(), "Invalid log level: loud");
+
+        let format = configuration(pixy_core::config::ServerConfig {
+            log_format: Some(String::from("xml")),
+            ..Default::default()
+        });
+
+        assert!(setup(&format, false)
+            .unwrap_err()
+            .starts_with("Unsupported log format xml"));
+    }
+}
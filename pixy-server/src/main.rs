@@ -1,20 +1,39 @@
-use pixy_server::{config::ServerConfiguration, run_server_with};
-use tracing::Level;
-use tracing_subscriber::fmt;
+use std::process::ExitCode;
+
+use pixy_server::{
+    config::{ServerConfiguration, ServerOverrides},
+    logging, run_server_with,
+};
 
 #[tokio::main]
-async fn main() {
-    let server_configs = ServerConfiguration::build().unwrap();
-    let log_level = match server_configs.log_level.as_str() {
-        "debug" => Level::DEBUG,
-        "info" => Level::INFO,
-        "warn" => Level::WARN,
-        "error" => Level::ERROR,
-        "trace" => Level::TRACE,
-        _ => Level::INFO,
+async fn main() -> ExitCode {
+    let server_configs = match ServerConfiguration::load(&ServerOverrides::default()) {
+        Ok(server_configs) => server_configs,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let telemetry = match logging::setup(&server_configs, true) {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
     };
 
-    fmt().compact().with_max_level(log_level).init();
+    let result = run_server_with(server_configs).await;
+
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown();
+    }
 
-    run_server_with(server_configs).await;
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,293 @@
+//! Reports whether the gateway is ready to relay readings, as opposed to
+//! `/healthz`, which only reports that the server answers.
+//!
+//! The gateway is ready once its configuration is loaded, and while enough of its
+//! targets are enabled and can be delivered to. The criteria are set in the
+//! configuration of the server.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, MethodRouter},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use pixy_core::{status::TargetStatus, Gateway};
+use serde::Serialize;
+
+use crate::config::ServerConfiguration;
+
+/// When the gateway is considered ready.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ReadinessCriteria {
+    /// The fewest targets that must be enabled.
+    pub min_targets: usize,
+    /// The most enabled targets that can be failing. When unset, the gateway is
+    /// only unready if every enabled target is failing.
+    pub max_failing: Option<usize>,
+    /// The most readings that can wait in the backlog of any target.
+    pub max_backlog: Option<usize>,
+}
+
+impl From<&ServerConfiguration> for ReadinessCriteria {
+    fn from(config: &ServerConfiguration) -> Self {
+        Self {
+            min_targets: config.ready_min_targets,
+            max_failing: config.ready_max_failing,
+            max_backlog: config.ready_max_backlog,
+        }
+    }
+}
+
+/// The readiness of a single target.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TargetReadiness {
+    name: String,
+    enabled: bool,
+    failing: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    circuit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backlog: Option<usize>,
+    last_success: Option<DateTime<Utc>>,
+}
+
+impl From<TargetStatus> for TargetReadiness {
+    fn from(status: TargetStatus) -> Self {
+        Self {
+            failing: status.is_failing(),
+            name: status.name,
+            enabled: status.enabled,
+            circuit: status.circuit,
+            backlog: status.backlog,
+            last_success: status.last_success,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Readiness {
+    ready: bool,
+    loaded: bool,
+    loaded_at: Option<DateTime<Utc>>,
+    enabled_targets: usize,
+    failing_targets: usize,
+    /// Why the gateway is not ready, empty when it is.
+    reasons: Vec<String>,
+    targets: Vec<TargetReadiness>,
+}
+
+impl Readiness {
+    fn check(
+        loaded_at: Option<DateTime<Utc>>,
+        targets: Vec<TargetStatus>,
+        criteria: &ReadinessCriteria,
+    ) -> Self {
+        let targets: Vec<TargetReadiness> = targets.into_iter().map(Into::into).collect();
+        let enabled = targets.iter().filter(|target| target.enabled).count();
+        let failing = targets.iter().filter(|target| target.failing).count();
+
+        let mut reasons = Vec::new();
+
+        if loaded_at.is_none() {
+            reasons.push(String::from("The configuration is not loaded"));
+        }
+
+        if enabled < criteria.min_targets {
+            reasons.push(format!(
+                "{} targets are enabled, at least {} are required",
+                enabled, criteria.min_targets
+            ));
+        }
+
+        match criteria.max_failing {
+            Some(max) if failing > max => reasons.push(format!(
+                "{} targets are failing, at most {} are allowed",
+                failing, max
+            )),
+            None if enabled > 0 && failing == enabled => {
+                reasons.push(String::from("Every enabled target is failing"))
+            }
+            _ => {}
+        }
+
+        if let Some(max) = criteria.max_backlog {
+            for target in &targets {
+                match target.backlog {
+                    Some(backlog) if backlog > max => reasons.push(format!(
+                        "{} readings are waiting in the backlog of {}, at most {} are allowed",
+                        backlog, target.name, max
+                    )),
+                    _ => {}
+                }
+            }
+        }
+
+        Self {
+            ready: reasons.is_empty(),
+            loaded: loaded_at.is_some(),
+            loaded_at,
+            enabled_targets: enabled,
+            failing_targets: failing,
+            reasons,
+            targets,
+        }
+    }
+}
+
+/// Creates the `/readyz` route, which responds with `503` when the gateway does not
+/// meet the criteria.
+pub(crate) fn route(criteria: ReadinessCriteria) -> MethodRouter<Arc<dyn Gateway>> {
+    let criteria = Arc::new(criteria);
+
+    get(move |State(gateway): State<Arc<dyn Gateway>>| {
+        let criteria = criteria.clone();
+        async move { readyz(gateway, &criteria) }
+    })
+}
+
+fn readyz(
+    gateway: Arc<dyn Gateway>,
+    criteria: &ReadinessCriteria,
+) -> (StatusCode, Json<Readiness>) {
+    let readiness = Readiness::check(gateway.loaded_at(), gateway.targets(), criteria);
+
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(readiness))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(name: &str, enabled: bool, failing: bool) -> TargetStatus {
+        TargetStatus {
+            name: String::from(name),
+            enabled,
+            config: String::new(),
+            circuit: None,
+            backlog: None,
+            deliveries: 1,
+            failures: failing as u64,
+            last_success: (!failing).then(Utc::now),
+            last_failure: failing.then(Utc::now),
+            last_error: None,
+        }
+    }
+
+    fn criteria() -> ReadinessCriteria {
+        ReadinessCriteria {
+            min_targets: 1,
+            max_failing: None,
+            max_backlog: None,
+        }
+    }
+
+    #[test]
+    fn test_ready_with_a_working_target() {
+        let readiness = Readiness::check(
+            Some(Utc::now()),
+            vec![target("influx", true, false), target("echo", true, true)],
+            &criteria(),
+        );
+
+        assert!(readiness.ready, "{:?}", readiness.reasons);
+        assert_eq!(readiness.enabled_targets, 2);
+        assert_eq!(readiness.failing_targets, 1);
+    }
+
+    #[test]
+    fn test_unready_when_not_loaded() {
+        let readiness = Readiness::check(None, vec![target("influx", true, false)], &criteria());
+
+        assert!(!readiness.ready);
+        assert!(!readiness.loaded);
+    }
+
+    #[test]
+    fn test_unready_without_enabled_targets() {
+        let readiness = Readiness::check(
+            Some(Utc::now()),
+            vec![target("influx", false, false)],
+            &criteria(),
+        );
+
+        assert!(!readiness.ready);
+
+        let readiness = Readiness::check(
+            Some(Utc::now()),
+            vec![target("influx", false, false)],
+            &ReadinessCriteria {
+                min_targets: 0,
+                ..criteria()
+            },
+        );
+
+        assert!(readiness.ready);
+    }
+
+    #[test]
+    fn test_unready_when_targets_fail() {
+        let targets = || vec![target("influx", true, true), target("echo", true, false)];
+
+        assert!(
+            !Readiness::check(
+                Some(Utc::now()),
+                vec![target("influx", true, true)],
+                &criteria()
+            )
+            .ready
+        );
+
+        assert!(
+            !Readiness::check(
+                Some(Utc::now()),
+                targets(),
+                &ReadinessCriteria {
+                    max_failing: Some(0),
+                    ..criteria()
+                }
+            )
+            .ready
+        );
+
+        assert!(
+            Readiness::check(
+                Some(Utc::now()),
+                targets(),
+                &ReadinessCriteria {
+                    max_failing: Some(1),
+                    ..criteria()
+                }
+            )
+            .ready
+        );
+    }
+
+    #[test]
+    fn test_unready_when_backlog_is_full() {
+        let mut influx = target("influx", true, false);
+        influx.backlog = Some(20);
+
+        let readiness = Readiness::check(
+            Some(Utc::now()),
+            vec![influx],
+            &ReadinessCriteria {
+                max_backlog: Some(10),
+                ..criteria()
+            },
+        );
+
+        assert!(!readiness.ready);
+        assert!(readiness.reasons[0].contains("influx"));
+    }
+}
@@ -0,0 +1,212 @@
+//! Live streams of the readings accepted by the gateway, over Server-Sent Events
+//! and WebSockets.
+//!
+//! Clients can limit a stream to the readings of a single board with the `uid` and
+//! `nickname` query parameters. Clients that are too slow to keep up miss the
+//! oldest readings rather than holding up the gateway. The number of clients
+//! streaming at once is limited, and further clients are turned away.
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::get,
+    Router,
+};
+use futures::stream::{self, Stream};
+use pixy_core::{Gateway, SensorMessage};
+use serde::Deserialize;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, warn};
+
+/// The readings a client wants to receive.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct StreamFilter {
+    uid: Option<String>,
+    nickname: Option<String>,
+}
+
+impl StreamFilter {
+    fn matches(&self, reading: &SensorMessage) -> bool {
+        self.uid.as_deref().is_none_or(|uid| uid == reading.uid())
+            && self
+                .nickname
+                .as_deref()
+                .is_none_or(|nickname| nickname == reading.nickname())
+    }
+}
+
+/// Receives the next reading that matches the filter, or `None` once the gateway
+/// stops publishing readings.
+async fn next_reading(
+    readings: &mut Receiver<SensorMessage>,
+    filter: &StreamFilter,
+) -> Option<String> {
+    loop {
+        match readings.recv().await {
+            Ok(reading) if filter.matches(&reading) => {
+                return serde_json::to_string(&reading).ok();
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(missed)) => {
+                warn!(missed, "Stream client fell behind, skipping readings");
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+fn unavailable() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Live readings are not available",
+    )
+        .into_response()
+}
+
+fn too_many_clients() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Too many clients are streaming the readings",
+    )
+        .into_response()
+}
+
+/// Creates the `/stream` and `/ws` routes, which serve at most `max_clients`
+/// clients at once.
+pub(crate) fn router(max_clients: usize) -> Router<Arc<dyn Gateway>> {
+    let clients = Arc::new(Semaphore::new(max_clients));
+    let ws_clients = clients.clone();
+
+    Router::new()
+        .route(
+            "/stream",
+            get(move |state, query| sse(state, query, clients.clone())),
+        )
+        .route(
+            "/ws",
+            get(move |state, query, upgrade| websocket(state, query, upgrade, ws_clients.clone())),
+        )
+}
+
+/// The events of a stream. The permit of the client is held until the stream is
+/// dropped, when the client goes away.
+fn events(
+    readings: Receiver<SensorMessage>,
+    filter: StreamFilter,
+    permit: OwnedSemaphorePermit,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    stream::unfold(
+        (readings, filter, permit),
+        |(mut readings, filter, permit)| async move {
+            let data = next_reading(&mut readings, &filter).await?;
+            let event = Event::default().event("reading").data(data);
+
+            Some((Ok(event), (readings, filter, permit)))
+        },
+    )
+}
+
+/// Streams the readings as Server-Sent Events, with one `reading` event per
+/// reading.
+async fn sse(
+    State(gateway): State<Arc<dyn Gateway>>,
+    Query(filter): Query<StreamFilter>,
+    clients: Arc<Semaphore>,
+) -> Response {
+    let Some(readings) = gateway.subscribe() else {
+        return unavailable();
+    };
+
+    let Ok(permit) = clients.try_acquire_owned() else {
+        return too_many_clients();
+    };
+
+    debug!(?filter, "Stream client connected");
+
+    Sse::new(events(readings, filter, permit))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Streams the readings over a WebSocket, with one text message per reading.
+async fn websocket(
+    State(gateway): State<Arc<dyn Gateway>>,
+    Query(filter): Query<StreamFilter>,
+    upgrade: WebSocketUpgrade,
+    clients: Arc<Semaphore>,
+) -> Response {
+    let Some(readings) = gateway.subscribe() else {
+        return unavailable();
+    };
+
+    let Ok(permit) = clients.try_acquire_owned() else {
+        return too_many_clients();
+    };
+
+    debug!(?filter, "WebSocket client connected");
+
+    upgrade.on_upgrade(move |socket| forward(socket, readings, filter, permit))
+}
+
+async fn forward(
+    mut socket: WebSocket,
+    mut readings: Receiver<SensorMessage>,
+    filter: StreamFilter,
+    _permit: OwnedSemaphorePermit,
+) {
+    loop {
+        tokio::select! {
+            reading = next_reading(&mut readings, &filter) => {
+                let Some(reading) = reading else {
+                    let _ = socket.send(Message::Close(None)).await;
+                    return;
+                };
+
+                if socket.send(Message::Text(reading)).await.is_err() {
+                    return;
+                }
+            }
+            // Messages from the client are ignored, other than to notice that
+            // it went away
+            message = socket.recv() => {
+                if !matches!(message, Some(Ok(_))) {
+                    debug!("WebSocket client disconnected");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_matches_board() {
+        let reading = SensorMessage::sample();
+
+        assert!(StreamFilter::default().matches(&reading));
+        assert!(StreamFilter {
+            uid: Some(String::from("pixy-test")),
+            nickname: None,
+        }
+        .matches(&reading));
+        assert!(!StreamFilter {
+            uid: Some(String::from("pixy-test")),
+            nickname: Some(String::from("office")),
+        }
+        .matches(&reading));
+    }
+}
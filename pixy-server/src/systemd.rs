@@ -0,0 +1,307 @@
+//! Reports the state of the server to systemd, for units with `Type=notify`.
+//!
+//! The server tells systemd when it is ready to accept readings and when it is
+//! stopping, and keeps a status line with the readings relayed and the failing
+//! targets. When the unit sets `WatchdogSec=`, the server also pings the watchdog,
+//! but only while the gateway answers a self-check, so that systemd restarts a
+//! gateway that is stuck.
+//!
+//! The self-check requires the loop that starts the periodic work of the gateway
+//! to have run within half the watchdog timeout, and the state of the targets to
+//! be read within a quarter of it. A ping is therefore never sent later than three
+//! quarters of the timeout. The periodic work itself delivers to the targets, so
+//! it is not waited for, and a slow target cannot get the gateway restarted.
+
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pixy_core::{status::TargetStatus, Gateway};
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// Sends notifications to the socket of the service manager.
+#[derive(Debug)]
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+}
+
+impl Notifier {
+    /// Connects to the socket in `NOTIFY_SOCKET`, if the server was started by
+    /// systemd with notifications enabled.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("NOTIFY_SOCKET").ok()?;
+
+        // Sockets in the abstract namespace are written with a leading `@`
+        let address = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name),
+            None => SocketAddr::from_pathname(&path),
+        };
+
+        let socket = UnixDatagram::unbound();
+
+        match (address, socket) {
+            (Ok(address), Ok(socket)) => Some(Self { socket, address }),
+            (Err(e), _) | (_, Err(e)) => {
+                warn!(error = %e, "Error connecting to the systemd notification socket");
+                None
+            }
+        }
+    }
+
+    /// Sends a notification, made of `KEY=value` lines.
+    pub fn notify(&self, state: &str) {
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.address) {
+            warn!(error = %e, "Error notifying systemd");
+        }
+    }
+
+    pub fn ready(&self, status: &str) {
+        self.notify(&format!("READY=1\nSTATUS={}", status));
+    }
+
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1\nSTATUS=Stopping");
+    }
+
+    pub fn status(&self, status: &str) {
+        self.notify(&format!("STATUS={}", status));
+    }
+
+    pub fn watchdog(&self) {
+        self.notify("WATCHDOG=1");
+    }
+}
+
+/// The last time the loop that starts the periodic work of the gateway ran.
+#[derive(Debug)]
+pub struct Heartbeat(Mutex<Instant>);
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self(Mutex::new(Instant::now()))
+    }
+}
+
+impl Heartbeat {
+    /// Records that the loop that starts the periodic work of the gateway ran.
+    pub fn beat(&self) {
+        *self.0.lock().expect("heartbeat lock poisoned") = Instant::now();
+    }
+
+    /// The time since the loop that starts the periodic work of the gateway ran.
+    pub(crate) fn age(&self) -> Duration {
+        self.0.lock().expect("heartbeat lock poisoned").elapsed()
+    }
+}
+
+/// Summarises the state of the targets for the status line of the unit.
+pub fn status_line(targets: &[TargetStatus]) -> String {
+    let relayed: u64 = targets
+        .iter()
+        .map(|target| target.deliveries - target.failures)
+        .sum();
+
+    let failing: Vec<&str> = targets
+        .iter()
+        .filter(|target| target.is_failing())
+        .map(|target| target.name.as_str())
+        .collect();
+
+    if failing.is_empty() {
+        format!("Relayed {} readings, all targets healthy", relayed)
+    } else {
+        format!(
+            "Relayed {} readings, failing targets: {}",
+            relayed,
+            failing.join(", ")
+        )
+    }
+}
+
+/// How often the watchdog is pinged, half the timeout set by the unit, if this
+/// process is the one being watched.
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+
+    let usec: u64 = usec?.parse().ok().filter(|usec| *usec > 0)?;
+
+    Some(Duration::from_micros(usec) / 2)
+}
+
+/// Checks that the gateway starts its periodic work and answers, and returns the
+/// status line of the unit. The state of the targets is read on a blocking
+/// thread, so that a gateway stuck on a lock cannot hold up the runtime.
+async fn self_check(
+    gateway: &Arc<dyn Gateway>,
+    heartbeat: &Heartbeat,
+    max_age: Duration,
+    timeout: Duration,
+) -> Option<String> {
+    let age = heartbeat.age();
+
+    if age > max_age {
+        warn!(
+            ?age,
+            "Gateway has not started its periodic work, skipping the watchdog ping"
+        );
+        return None;
+    }
+
+    let gateway = Arc::clone(gateway);
+    let check = tokio::task::spawn_blocking(move || status_line(&gateway.targets()));
+
+    match tokio::time::timeout(timeout, check).await {
+        Ok(Ok(status)) => Some(status),
+        Ok(Err(e)) => {
+            warn!(error = %e, "Gateway self-check failed");
+            None
+        }
+        Err(_) => {
+            warn!("Gateway self-check timed out, skipping the watchdog ping");
+            None
+        }
+    }
+}
+
+/// Keeps the status line up to date and, if the unit has a watchdog, pings it
+/// after every successful self-check of the gateway. Runs until the server stops.
+pub async fn supervise(
+    notifier: Arc<Notifier>,
+    gateway: Arc<dyn Gateway>,
+    heartbeat: Arc<Heartbeat>,
+) {
+    let watchdog = watchdog_interval(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+    );
+
+    let interval = watchdog.unwrap_or(Duration::from_secs(30));
+    debug!(?watchdog, "Reporting the status to systemd");
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let Some(status) = self_check(&gateway, &heartbeat, interval, interval / 2).await else {
+            continue;
+        };
+
+        notifier.status(&status);
+
+        if watchdog.is_some() {
+            notifier.watchdog();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use pixy_core::SensorMessage;
+
+    #[derive(Debug)]
+    struct MockGateway {}
+
+    #[async_trait]
+    impl Gateway for MockGateway {
+        async fn handle_reading(&self, _reading: SensorMessage) {}
+    }
+
+    fn target(name: &str, deliveries: u64, failures: u64) -> TargetStatus {
+        TargetStatus {
+            name: name.to_string(),
+            enabled: true,
+            config: String::new(),
+            circuit: None,
+            backlog: None,
+            deliveries,
+            failures,
+            last_success: None,
+            last_failure: None,
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn test_status_line_lists_failing_targets() {
+        let mut failing = target("influx", 3, 2);
+        failing.last_failure = Some(chrono::Utc::now());
+
+        assert_eq!(
+            status_line(&[target("echo", 5, 0)]),
+            "Relayed 5 readings, all targets healthy"
+        );
+        assert_eq!(
+            status_line(&[target("echo", 5, 0), failing]),
+            "Relayed 6 readings, failing targets: influx"
+        );
+    }
+
+    #[test]
+    fn test_watchdog_interval() {
+        let pid = std::process::id().to_string();
+
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000"), Some(&pid)),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(watchdog_interval(Some("30000000"), Some("1")), None);
+        assert_eq!(watchdog_interval(None, None), None);
+    }
+
+    #[tokio::test]
+    async fn test_self_check_requires_recent_heartbeat() {
+        let gateway: Arc<dyn Gateway> = Arc::new(MockGateway {});
+        let max_age = Duration::from_secs(15);
+        let timeout = Duration::from_secs(5);
+
+        let heartbeat = Heartbeat::default();
+
+        assert_eq!(
+            self_check(&gateway, &heartbeat, max_age, timeout).await,
+            Some(String::from("Relayed 0 readings, all targets healthy"))
+        );
+
+        let stale = Heartbeat(Mutex::new(Instant::now() - Duration::from_secs(60)));
+
+        assert_eq!(self_check(&gateway, &stale, max_age, timeout).await, None);
+
+        stale.beat();
+
+        assert!(self_check(&gateway, &stale, max_age, timeout)
+            .await
+            .is_some());
+    }
+
+    #[test]
+    fn test_notifier_sends_state() {
+        let path = std::env::temp_dir().join(format!("pixy-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        let notifier = Notifier {
+            socket: UnixDatagram::unbound().unwrap(),
+            address: SocketAddr::from_pathname(&path).unwrap(),
+        };
+
+        notifier.ready("Listening");
+
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+
+        assert_eq!(&buf[..len], b"READY=1\nSTATUS=Listening");
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
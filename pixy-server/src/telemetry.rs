@@ -0,0 +1,163 @@
+//! Exports traces and metrics to an OpenTelemetry collector over OTLP.
+//!
+//! The exporter is only set up when an endpoint is configured, either directly or
+//! through the standard `OTEL_EXPORTER_OTLP_ENDPOINT` environment variable. The
+//! protocol defaults to gRPC, and the service name to `pixy`.
+
+use opentelemetry::{global, trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::{MetricExporter, Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    metrics::{PeriodicReader, SdkMeterProvider},
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Tracer, TracerProvider},
+    Resource,
+};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// The protocol used to send the telemetry to the collector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OtlpProtocol {
+    Grpc,
+    Http,
+}
+
+impl std::str::FromStr for OtlpProtocol {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "grpc" => Ok(OtlpProtocol::Grpc),
+            "http" | "http/protobuf" => Ok(OtlpProtocol::Http),
+            _ => Err(format!(
+                "Unsupported OTLP protocol {}, expected grpc or http/protobuf",
+                value
+            )),
+        }
+    }
+}
+
+/// The providers exporting the telemetry, which must be shut down before exiting
+/// so that the pending telemetry is sent.
+#[derive(Debug)]
+pub struct Telemetry {
+    tracer_provider: TracerProvider,
+    meter_provider: SdkMeterProvider,
+    tracer: Tracer,
+}
+
+impl Telemetry {
+    /// Sets up the export of traces and metrics, and the propagation of the trace
+    /// context in the W3C `traceparent` header. Returns `None` if no endpoint is
+    /// configured. Must be called from within a Tokio runtime.
+    ///
+    /// ## Arguments
+    ///
+    /// * `endpoint` - The URL of the collector. Falls back to the
+    ///   `OTEL_EXPORTER_OTLP_ENDPOINT` environment variable.
+    /// * `protocol` - Either `grpc` or `http/protobuf`. Falls back to the
+    ///   `OTEL_EXPORTER_OTLP_PROTOCOL` environment variable, then to `grpc`.
+    pub fn init(endpoint: Option<&str>, protocol: Option<&str>) -> Result<Option<Self>, String> {
+        let Some(endpoint) = endpoint
+            .map(String::from)
+            .or_else(|| std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok())
+            .filter(|endpoint| !endpoint.is_empty())
+        else {
+            return Ok(None);
+        };
+
+        let protocol: OtlpProtocol = protocol
+            .map(String::from)
+            .or_else(|| std::env::var("OTEL_EXPORTER_OTLP_PROTOCOL").ok())
+            .unwrap_or_else(|| String::from("grpc"))
+            .parse()?;
+
+        let service_name =
+            std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| String::from("pixy"));
+        let resource = Resource::new([KeyValue::new("service.name", service_name)]);
+
+        let (spans, metrics) = match protocol {
+            OtlpProtocol::Grpc => (
+                SpanExporter::builder()
+                    .with_tonic()
+                    .with_endpoint(&endpoint)
+                    .build(),
+                MetricExporter::builder()
+                    .with_tonic()
+                    .with_endpoint(&endpoint)
+                    .build(),
+            ),
+            // The HTTP endpoints of each signal are under the base URL
+            OtlpProtocol::Http => (
+                SpanExporter::builder()
+                    .with_http()
+                    .with_protocol(Protocol::HttpBinary)
+                    .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+                    .build(),
+                MetricExporter::builder()
+                    .with_http()
+                    .with_protocol(Protocol::HttpBinary)
+                    .with_endpoint(format!("{}/v1/metrics", endpoint.trim_end_matches('/')))
+                    .build(),
+            ),
+        };
+
+        let spans = spans.map_err(|e| format!("Error creating OTLP span exporter: {}", e))?;
+        let metrics = metrics.map_err(|e| format!("Error creating OTLP metric exporter: {}", e))?;
+
+        let tracer_provider = TracerProvider::builder()
+            .with_batch_exporter(spans, runtime::Tokio)
+            .with_resource(resource.clone())
+            .build();
+
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(metrics, runtime::Tokio).build())
+            .with_resource(resource)
+            .build();
+
+        let tracer = tracer_provider.tracer("pixy");
+
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        global::set_tracer_provider(tracer_provider.clone());
+        global::set_meter_provider(meter_provider.clone());
+
+        Ok(Some(Self {
+            tracer_provider,
+            meter_provider,
+            tracer,
+        }))
+    }
+
+    /// The layer sending the spans of the application to the collector.
+    pub fn layer<S>(&self) -> OpenTelemetryLayer<S, Tracer>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.tracer.clone())
+    }
+
+    /// Sends the pending telemetry, and stops the exporters.
+    pub fn shutdown(&self) {
+        if let Err(e) = self.tracer_provider.shutdown() {
+            eprintln!("Error shutting down the trace exporter: {}", e);
+        }
+
+        if let Err(e) = self.meter_provider.shutdown() {
+            eprintln!("Error shutting down the metric exporter: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_protocol() {
+        assert_eq!("grpc".parse(), Ok(OtlpProtocol::Grpc));
+        assert_eq!("http/protobuf".parse(), Ok(OtlpProtocol::Http));
+        assert!("http/json".parse::<OtlpProtocol>().is_err());
+    }
+}
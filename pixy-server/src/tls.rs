@@ -0,0 +1,73 @@
+//! Serves the TCP addresses over TLS, with a certificate chain and a private key
+//! read from PEM files. Unix sockets are only reachable from the same host, and
+//! are always served without TLS.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+/// How long a client has to complete the TLS handshake.
+pub(crate) const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn open(path: &str) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("Error opening {}: {}", path, e))
+}
+
+/// Creates the acceptor of the TLS connections from the certificate chain and
+/// the private key files. HTTP/2 and HTTP/1.1 are both offered.
+pub(crate) fn acceptor(cert: &str, key: &str) -> Result<TlsAcceptor, String> {
+    let certs = rustls_pemfile::certs(&mut open(cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid TLS certificate {}: {}", cert, e))?;
+
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {}", cert));
+    }
+
+    let private_key = rustls_pemfile::private_key(&mut open(key)?)
+        .map_err(|e| format!("Invalid TLS key {}: {}", key, e))?
+        .ok_or_else(|| format!("No private key found in {}", key))?;
+
+    let mut config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_no_client_auth()
+            .with_single_cert(certs, private_key)
+            .map_err(|e| format!("Invalid TLS certificate or key: {}", e))?;
+
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/localhost.pem");
+    const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/localhost-key.pem");
+
+    #[test]
+    fn test_acceptor_reads_certificate_and_key() {
+        assert!(acceptor(CERT, KEY).is_ok());
+    }
+
+    #[test]
+    fn test_acceptor_rejects_missing_key() {
+        assert_eq!(
+            acceptor(CERT, CERT).err(),
+            Some(format!("No private key found in {}", CERT))
+        );
+        assert!(acceptor("/nonexistent/cert.pem", KEY)
+            .err()
+            .unwrap()
+            .starts_with("Error opening /nonexistent/cert.pem"));
+    }
+}
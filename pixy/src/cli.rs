@@ -17,12 +17,40 @@ use clap::{Args, Parser, Subcommand};
 /// The Pixy CLI definition
 pub struct Cli {
     /// Increases logging verbosity, up to max of 3
-    #[arg(short, long, action = clap::ArgAction::Count)]
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
     pub(crate) verbose: u8,
 
-    /// The config file to use.
-    #[arg(short, long, global = true, default_value = "pixy.yaml")]
-    pub(crate) config: String,
+    /// The config file to use. Defaults to `pixy.yaml`, or to the
+    /// `PIXY_CONFIG_FILE` environment variable when serving.
+    #[arg(short, long, global = true)]
+    pub(crate) config: Option<String>,
+
+    /// The URL of an OpenTelemetry collector to send traces and metrics to. Falls
+    /// back to the `OTEL_EXPORTER_OTLP_ENDPOINT` environment variable.
+    #[arg(long, global = true)]
+    pub(crate) otlp_endpoint: Option<String>,
+
+    /// The protocol used to send telemetry to the collector, `grpc` or
+    /// `http/protobuf`. Falls back to the `OTEL_EXPORTER_OTLP_PROTOCOL`
+    /// environment variable, then to `grpc`.
+    #[arg(long, global = true)]
+    pub(crate) otlp_protocol: Option<String>,
+
+    /// The format of the logs. Defaults to `pretty`, or to `compact` when serving.
+    #[arg(long, global = true, value_parser = ["pretty", "compact", "json", "logfmt"])]
+    pub(crate) log_format: Option<String>,
+
+    /// A file to write the logs to instead of standard output.
+    #[arg(long, global = true)]
+    pub(crate) log_file: Option<String>,
+
+    /// The size in megabytes after which the log file is rotated. Defaults to 10.
+    #[arg(long, global = true)]
+    pub(crate) log_max_size: Option<u64>,
+
+    /// The number of rotated log files that are kept. Defaults to 5.
+    #[arg(long, global = true)]
+    pub(crate) log_max_files: Option<usize>,
 
     #[command(subcommand)]
     pub(crate) command: Commands,
@@ -37,12 +65,16 @@ pub enum Commands {
     Emit(EmitArgs),
     /// Starts a server instance of Pixy.
     Serve(ServeArgs),
+    /// Delivers the dead letters stored in the dead letter file to their targets.
+    Replay(ReplayArgs),
+    /// Inspects the configuration of the server.
+    Config(ConfigArgs),
 }
 
 #[derive(Args, Debug)]
 pub struct ValidateArgs {
     #[arg(from_global)]
-    pub(crate) config: String,
+    pub(crate) config: Option<String>,
 }
 
 /// A subcommand to emit sensor data to the configured targets. This emulates
@@ -59,20 +91,157 @@ pub struct EmitArgs {
     pub(crate) file: bool,
 
     #[arg(from_global)]
-    pub(crate) config: String,
+    pub(crate) config: Option<String>,
 }
 
 /// Arguments for starting a server instance of Pixy.
-#[derive(Args, Debug)]
+#[derive(Args)]
 pub struct ServeArgs {
-    /// The port to run the server on.
-    #[arg(short, long, default_value = "9147")]
-    pub(crate) port: u16,
+    /// The port to run the server on, when no listen address is set. Defaults
+    /// to 9147.
+    #[arg(short, long)]
+    pub(crate) port: Option<u16>,
+
+    /// An address to listen on, `host:port` (i.e. `127.0.0.1:9147` or
+    /// `[::]:9147`) or `unix:/path/to/socket`. Can be repeated. Ignored when
+    /// sockets are passed by systemd socket activation.
+    #[arg(short, long)]
+    pub(crate) listen: Vec<String>,
+
+    /// The permissions of the Unix sockets, in octal (i.e. `660`).
+    #[arg(long)]
+    pub(crate) socket_mode: Option<String>,
+
+    /// The PEM file of the certificate chain to serve over TLS on the TCP
+    /// addresses. Requires `--tls-key`.
+    #[arg(long, requires = "tls_key")]
+    pub(crate) tls_cert: Option<String>,
+
+    /// The PEM file of the private key of the TLS certificate.
+    #[arg(long, requires = "tls_cert")]
+    pub(crate) tls_key: Option<String>,
 
     /// Whether to enable the `/echo` endpoint. Not recommended for production.
     #[arg(long, default_value_t = false)]
     pub(crate) enable_echo: bool,
 
+    /// Whether to enable the `/dashboard` status page.
+    #[arg(long, default_value_t = false)]
+    pub(crate) enable_dashboard: bool,
+
+    /// Whether to stream the readings on `/stream` and `/ws`.
+    #[arg(long, default_value_t = false)]
+    pub(crate) enable_stream: bool,
+
+    /// The most clients that can stream the readings at once. Defaults to 16.
+    #[arg(long)]
+    pub(crate) stream_max_clients: Option<u64>,
+
+    /// The largest reading accepted on `/data`, in kilobytes. Defaults to 2048.
+    #[arg(long)]
+    pub(crate) max_payload_size: Option<u64>,
+
+    /// The bearer token of the admin API. The admin API is only served when a
+    /// token is set.
+    #[arg(long)]
+    #[cfg_attr(
+        feature = "env",
+        arg(env = "PIXY_ADMIN_TOKEN", hide_env_values = true)
+    )]
+    pub(crate) admin_token: Option<String>,
+
+    /// The fewest enabled targets for `/readyz` to report the gateway as ready.
+    /// Defaults to 1.
+    #[arg(long)]
+    pub(crate) ready_min_targets: Option<u64>,
+
+    /// The most failing targets for `/readyz` to report the gateway as ready.
+    /// When unset, the gateway is only unready if every enabled target fails.
+    #[arg(long)]
+    pub(crate) ready_max_failing: Option<u64>,
+
+    /// The most readings waiting in the backlog of a target for `/readyz` to
+    /// report the gateway as ready.
+    #[arg(long)]
+    pub(crate) ready_max_backlog: Option<u64>,
+
+    #[arg(from_global)]
+    pub(crate) config: Option<String>,
+
+    #[arg(from_global)]
+    pub(crate) otlp_endpoint: Option<String>,
+
+    #[arg(from_global)]
+    pub(crate) otlp_protocol: Option<String>,
+
+    #[arg(from_global)]
+    pub(crate) log_format: Option<String>,
+
+    #[arg(from_global)]
+    pub(crate) log_file: Option<String>,
+
+    #[arg(from_global)]
+    pub(crate) log_max_size: Option<u64>,
+
+    #[arg(from_global)]
+    pub(crate) log_max_files: Option<usize>,
+
+    #[arg(from_global)]
+    pub(crate) verbose: u8,
+}
+
+impl std::fmt::Debug for ServeArgs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServeArgs")
+            .field("port", &self.port)
+            .field("listen", &self.listen)
+            .field("socket_mode", &self.socket_mode)
+            .field("tls_cert", &self.tls_cert)
+            .field("tls_key", &self.tls_key)
+            .field("enable_echo", &self.enable_echo)
+            .field("enable_dashboard", &self.enable_dashboard)
+            .field("enable_stream", &self.enable_stream)
+            .field("stream_max_clients", &self.stream_max_clients)
+            .field("max_payload_size", &self.max_payload_size)
+            .field("admin_token", &self.admin_token.as_ref().map(|_| "******"))
+            .field("ready_min_targets", &self.ready_min_targets)
+            .field("ready_max_failing", &self.ready_max_failing)
+            .field("ready_max_backlog", &self.ready_max_backlog)
+            .field("config", &self.config)
+            .field("otlp_endpoint", &self.otlp_endpoint)
+            .field("otlp_protocol", &self.otlp_protocol)
+            .field("log_format", &self.log_format)
+            .field("log_file", &self.log_file)
+            .field("log_max_size", &self.log_max_size)
+            .field("log_max_files", &self.log_max_files)
+            .field("verbose", &self.verbose)
+            .finish()
+    }
+}
+
+/// Arguments for inspecting the configuration of the server.
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub(crate) command: ConfigCommands,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommands {
+    /// Prints the configuration `pixy serve` would run with, taking the flags,
+    /// the `PIXY_*` environment variables and the `server` section of the config
+    /// file into account. Secrets are redacted.
+    Show(ServeArgs),
+}
+
+/// Arguments for replaying the dead letters stored in the dead letter file. Dead
+/// letters that fail again are kept in the file.
+#[derive(Args, Debug)]
+pub struct ReplayArgs {
+    /// Only replays the dead letters of this target.
+    #[arg(short, long)]
+    pub(crate) target: Option<String>,
+
     #[arg(from_global)]
-    pub(crate) config: String,
+    pub(crate) config: Option<String>,
 }
@@ -17,28 +17,48 @@
 pub mod cli;
 pub mod logging;
 
-use pixy_core::validation::parse_configs;
+use pixy_core::config::ServerConfig;
+use pixy_core::validation::{parse_configs, validate};
 use pixy_core::{Gateway, SensorGateway, SensorMessage};
-use pixy_server::{config::ServerConfiguration, run_server_with};
+use pixy_server::{
+    config::{ServerConfiguration, ServerOverrides},
+    run_server_with,
+};
 use tracing::debug;
 
-pub async fn run(cli: cli::Cli) {
+/// The config file used when none is given.
+const DEFAULT_CONFIG_FILE: &str = "pixy.yaml";
+
+/// Runs the command, and returns the error it failed with, if any.
+pub async fn run(cli: cli::Cli) -> Result<(), String> {
+    // The server sets up its logs once its configuration is resolved
+    let telemetry = match cli.command {
+        cli::Commands::Serve(_) => None,
+        _ => logging::setup_logging(&cli),
+    };
+
     debug!("CLI config: {:?}", &cli);
 
     let result = match cli.command {
         cli::Commands::Validate(args) => run_validate(args),
         cli::Commands::Emit(args) => run_emit(args).await,
         cli::Commands::Serve(args) => run_server(args).await,
+        cli::Commands::Replay(args) => run_replay(args).await,
+        cli::Commands::Config(args) => match args.command {
+            cli::ConfigCommands::Show(args) => run_config_show(args),
+        },
     };
 
-    if let Err(e) = result {
-        println!("{}", e);
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown();
     }
+
+    result
 }
 
 fn run_validate(args: cli::ValidateArgs) -> Result<(), String> {
-    let file = args.config;
-    parse_configs(&file)?;
+    let file = args.config.as_deref().unwrap_or(DEFAULT_CONFIG_FILE);
+    validate(file)?;
 
     println!("Validation succeeded!");
 
@@ -46,11 +66,11 @@ fn run_validate(args: cli::ValidateArgs) -> Result<(), String> {
 }
 
 async fn run_emit(args: cli::EmitArgs) -> Result<(), String> {
-    let config_file = args.config;
+    let config_file = args.config.as_deref().unwrap_or(DEFAULT_CONFIG_FILE);
 
-    let config = parse_configs(&config_file)?;
+    let config = parse_configs(config_file)?;
 
-    let gateway = SensorGateway::from(config);
+    let gateway = SensorGateway::try_from(config)?;
 
     debug!("Gateway: {:?}", &gateway);
 
@@ -71,23 +91,90 @@ async fn run_emit(args: cli::EmitArgs) -> Result<(), String> {
         data
     };
 
-    let reading: SensorMessage =
+    let mut reading: SensorMessage =
         serde_json::from_str(&data).map_err(|e| format!("Error parsing sensor data: {}", e))?;
 
+    gateway.accept_reading(&mut reading)?;
+
     gateway.handle_reading(reading).await;
 
     Ok(())
 }
 
-async fn run_server(args: cli::ServeArgs) -> Result<(), String> {
-    let server_configs = ServerConfiguration {
-        config_file: args.config,
+async fn run_replay(args: cli::ReplayArgs) -> Result<(), String> {
+    let config = parse_configs(args.config.as_deref().unwrap_or(DEFAULT_CONFIG_FILE))?;
+
+    let gateway = SensorGateway::try_from(config)?;
+
+    let summary = gateway.replay_dead_letters(args.target.as_deref()).await?;
+
+    println!(
+        "Replayed {} dead letters, {} failed",
+        summary.replayed, summary.failed
+    );
+
+    Ok(())
+}
+
+/// Resolves the configuration of the server, with the flags taking precedence
+/// over the environment and the config file.
+fn server_configuration(args: cli::ServeArgs) -> Result<ServerConfiguration, String> {
+    let settings = ServerConfig {
         port: args.port,
-        log_level: String::from(""),
-        enable_echo: args.enable_echo,
+        listen: Some(args.listen).filter(|listen| !listen.is_empty()),
+        socket_mode: args.socket_mode,
+        tls_cert: args.tls_cert,
+        tls_key: args.tls_key,
+        // Switches can only turn settings on
+        enable_echo: args.enable_echo.then_some(true),
+        enable_dashboard: args.enable_dashboard.then_some(true),
+        enable_stream: args.enable_stream.then_some(true),
+        stream_max_clients: args.stream_max_clients,
+        max_payload_size: args.max_payload_size,
+        admin_token: args.admin_token,
+        log_level: (args.verbose > 0)
+            .then(|| logging::log_level(args.verbose).as_str().to_lowercase()),
+        log_format: args.log_format,
+        log_file: args.log_file,
+        log_max_size: args.log_max_size,
+        log_max_files: args.log_max_files.map(|files| files as u64),
+        otlp_endpoint: args.otlp_endpoint,
+        otlp_protocol: args.otlp_protocol,
+        ready_min_targets: args.ready_min_targets,
+        ready_max_failing: args.ready_max_failing,
+        ready_max_backlog: args.ready_max_backlog,
     };
 
-    run_server_with(server_configs).await;
+    ServerConfiguration::load(&ServerOverrides {
+        config_file: args.config,
+        default_config_file: Some(String::from(DEFAULT_CONFIG_FILE)),
+        settings,
+    })
+}
+
+async fn run_server(args: cli::ServeArgs) -> Result<(), String> {
+    let server_configs = server_configuration(args)?;
+
+    let telemetry = pixy_server::logging::setup(&server_configs, cfg!(feature = "colors"))?;
+
+    debug!("Server config: {:?}", server_configs.redacted());
+
+    let result = run_server_with(server_configs).await;
+
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown();
+    }
+
+    result
+}
+
+fn run_config_show(args: cli::ServeArgs) -> Result<(), String> {
+    let server_configs = server_configuration(args)?;
+
+    let output = serde_yaml::to_string(&server_configs.redacted())
+        .map_err(|e| format!("Error formatting the configuration: {}", e))?;
+
+    print!("{}", output);
 
     Ok(())
 }
@@ -5,27 +5,55 @@
 
 use crate::cli::Cli;
 
+use pixy_server::{
+    logging::{self, LogOptions},
+    telemetry::Telemetry,
+};
 use tracing::{trace, Level};
-use tracing_subscriber::FmtSubscriber;
+use tracing_subscriber::filter::LevelFilter;
 
 /// Configures the logging according to the CLI configurations. This
-/// enables coloured output (if using `colors` feature), and formats the
-/// output to include the file and line (if running in `debug` profile).
-pub fn setup_logging(cli: &Cli) {
-    let level = match cli.verbose {
-        0 => Level::WARN,
-        1 => Level::INFO,
-        2 => Level::DEBUG,
-        _ => Level::TRACE,
-    };
+/// enables coloured output (if using `colors` feature), and writes the
+/// logs in the chosen format, to standard output or to a log file.
+///
+/// When an OpenTelemetry collector is configured, the spans are also sent to it,
+/// and the returned telemetry must be shut down before exiting.
+pub fn setup_logging(cli: &Cli) -> Option<Telemetry> {
+    let level = log_level(cli.verbose);
+
+    let telemetry = Telemetry::init(cli.otlp_endpoint.as_deref(), cli.otlp_protocol.as_deref())
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            None
+        });
 
-    let subscriber = FmtSubscriber::builder()
-        .pretty()
-        .with_max_level(level)
-        .with_target(true)
-        .finish();
+    let options = LogOptions {
+        format: cli
+            .log_format
+            .as_deref()
+            .unwrap_or("pretty")
+            .parse()
+            .expect("log format is validated by the CLI"),
+        file: cli.log_file.clone(),
+        max_size: cli.log_max_size.unwrap_or(10) * 1024 * 1024,
+        max_files: cli.log_max_files.unwrap_or(5),
+        ansi: cfg!(feature = "colors"),
+    };
 
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    logging::init(&options, LevelFilter::from_level(level), telemetry.as_ref())
+        .expect("setting default subscriber failed");
 
     trace!("Finished setting up logging configuration!");
+
+    telemetry
+}
+
+/// The log level for the number of times the verbose flag was given.
+pub fn log_level(verbose: u8) -> Level {
+    match verbose {
+        0 => Level::WARN,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    }
 }
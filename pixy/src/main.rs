@@ -1,11 +1,17 @@
+use std::process::ExitCode;
+
 use clap::Parser;
 use pixy::cli::Cli;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
 
-    pixy::logging::setup_logging(&cli);
-
-    pixy::run(cli).await;
+    match pixy::run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
 }